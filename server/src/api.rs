@@ -1,51 +1,298 @@
 use cfg_if::cfg_if;
 use leptos::*;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Kind of an entry in a user's notes directory.
+///
+/// Variants are ordered the way they are listed: folders, notes, then resources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EntryKind {
+    Directory,
+    Note,
+    Resource,
+}
+
+/// A single entry of a directory listing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    /// Last modification time in seconds since the unix epoch
+    pub modified: Option<i64>,
+    pub size: u64,
+}
+
+/// A section of a note matching a search query.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// Path of the note relative to the notes directory
+    pub path: String,
+    pub title: String,
+    pub heading: Option<String>,
+    /// Id of the heading the section starts at
+    pub anchor: Option<String>,
+    pub snippet: Vec<SnippetPart>,
+    pub score: usize,
+}
+
+/// Part of a search snippet, highlighted if it matched the query.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+/// A note linking to the note being viewed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Backlink {
+    pub path: String,
+    pub title: String,
+    /// Sentence containing the link
+    pub context: String,
+}
+
+/// A tag and the number of notes carrying it or one of its nested tags.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// A file stored in the user's resources directory.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResourceFile {
+    /// Path relative to the resources directory
+    pub path: String,
+    /// Markdown embedding or linking the file
+    pub markdown: String,
+    pub size: u64,
+    /// Last modification time in seconds since the unix epoch
+    pub modified: Option<i64>,
+}
+
+/// A note or folder in the user's trash.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Path the entry was deleted from, relative to the notes directory
+    pub path: String,
+    /// Deletion time in seconds since the unix epoch
+    pub deleted: i64,
+    pub is_dir: bool,
+}
+
+/// A renamed or moved note or folder.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenamedEntry {
+    /// New path relative to the notes directory
+    pub path: String,
+    /// Notes whose links couldn't be updated
+    pub not_updated: Vec<String>,
+}
+
+/// A file accepted by the upload endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UploadedFile {
+    /// Path relative to the resources directory
+    pub path: String,
+    /// Markdown embedding or linking the file
+    pub markdown: String,
+}
+
+/// A note carrying a tag.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaggedNote {
+    pub path: String,
+    pub title: String,
+    /// Last modification time in seconds since the unix epoch
+    pub modified: Option<i64>,
+}
+
+/// Metadata parsed from the frontmatter of a note.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoteMetadata {
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Tags without their leading `#`
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    /// Alternative names the note can be linked to by
+    #[serde(default, deserialize_with = "deserialize_aliases")]
+    pub aliases: Vec<String>,
+}
+
+/// A heading of a rendered note, listed in its table of contents.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TocHeading {
+    pub(crate) level: u8,
+    /// Id of the heading on the rendered page
+    pub(crate) id: String,
+    /// Text of the heading without any formatting
+    pub(crate) text: String,
+    /// Headings nested below this one
+    pub(crate) children: Vec<TocHeading>,
+}
+
+/// A note rendered to HTML along with its metadata and table of contents.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RenderedNote {
+    pub html: String,
+    pub metadata: NoteMetadata,
+    pub toc: Vec<TocHeading>,
+    /// Version of the markdown the note was rendered from
+    pub version: String,
+}
+
+/// Hit and miss counters of the render cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of notes currently cached
+    pub len: usize,
+}
+
+/// The markdown of a note being edited.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoteSource {
+    pub content: String,
+    /// Hash of the content, empty if the note does not exist yet
+    pub version: String,
+}
+
+/// A past version of a note.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Revision {
+    /// Commit hash identifying the revision
+    pub id: String,
+    pub author: String,
+    /// Time of the revision in seconds since the unix epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+/// A line of a diff between two revisions of a note.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffLine {
+    Unchanged(String),
+    Added(String),
+    Removed(String),
+    /// A run of unchanged lines left out of the diff
+    Skipped(usize),
+}
+
+/// Frontmatter lists may also be written as a single comma separated string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Accepts `tags: [a, b]`, `tags: a, b` and `tags: "#a #b"`.
+fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let tags = match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(tags)) => tags.split([',', ' ']).map(String::from).collect(),
+        Some(OneOrMany::Many(tags)) => tags,
+        None => Vec::new(),
+    };
+
+    Ok(tags
+        .iter()
+        .map(|tag| tag.trim().trim_start_matches('#'))
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Accepts `aliases: [a, b]` and `aliases: a, b`.
+fn deserialize_aliases<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let aliases = match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(aliases)) => aliases.split(',').map(String::from).collect(),
+        Some(OneOrMany::Many(aliases)) => aliases,
+        None => Vec::new(),
+    };
+
+    Ok(aliases
+        .iter()
+        .map(|alias| alias.trim())
+        .filter(|alias| !alias.is_empty())
+        .map(String::from)
+        .collect())
+}
 
 cfg_if! { if #[cfg(feature = "ssr")] {
-use ammonia::Builder;
+use ammonia::clean_text;
 use axum_session_auth::{AuthSession, SessionSurrealPool};
+use chrono::{DateTime, Local};
 use crate::auth;
+use crate::cache::{RenderCache, RenderStamp};
+use crate::history;
+use crate::index::{rewrite_links, NotesIndex};
+use crate::markdown::{
+    callouts, extract_math, heading_ids, highlight_code_blocks, render_math, resource_links,
+    set_task, split_frontmatter, table_of_contents, task_checkboxes, wiki_links, LinkResolver,
+};
+use crate::paths;
+use crate::sanitizer::SanitizerSettings;
 use crate::settings::LazyNotesSettings;
+use crate::trash;
+use crate::uploads;
 use http::StatusCode;
 use leptos_axum::ResponseOptions;
-use pulldown_cmark::{html, Options, Parser};
-use std::fs::read_to_string;
+use pulldown_cmark::{html, Event, Options, Parser};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{
+    create_dir_all, metadata, read_dir, read_to_string, remove_file, rename, symlink_metadata, File,
+};
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
 use surrealdb::{engine::remote::ws::Client, Surreal};
 
-/// Handles sanitizing and converting markdown to html.
-fn convert_to_html(md_input: &str) -> String {
+/// Serializes writes to notes so checking for conflicts and saving is atomic.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Handles sanitizing and converting markdown to html, along with the
+/// table of contents of the headings in it.
+///
+/// `offset` is where `md_input` starts in the note's file. Task checkboxes get
+/// the offsets of their markers in the file from the same parse that renders
+/// them, so they can be toggled.
+pub(crate) fn convert_to_html(
+    md_input: &str,
+    links: &LinkResolver,
+    sanitizer: &SanitizerSettings,
+    offset: usize,
+) -> (String, Vec<TocHeading>) {
     let options = Options::all();
-    let parser = Parser::new_ext(md_input, options);
+    let (md_input, math, source) = extract_math(md_input);
+    let mut tasks = Vec::new();
+    let parser = Parser::new_ext(&md_input, options).into_offset_iter().map(|(event, range)| {
+        if let Event::TaskListMarker(_) = event {
+            tasks.push(offset + source.original(range.start));
+        }
+        event
+    });
+    let events = render_math(parser, &math);
+    let events = heading_ids(events.into_iter(), &sanitizer.id_prefix);
+    let toc = table_of_contents(&events, &sanitizer.id_prefix);
+    let events = callouts(task_checkboxes(events.into_iter(), &tasks).into_iter());
+    let events = resource_links(wiki_links(events.into_iter(), links).into_iter(), links);
+    let events = highlight_code_blocks(events);
 
     let mut dirty_md = String::new();
-    html::push_html(&mut dirty_md, parser);
-
-    // TODO: Allow specifying allowed tags in settings.toml
-    // TODO: Add MathML specs
-    Builder::default()
-        // .allowed_classes()
-        .id_prefix(Some("ln-"))
-        .add_tag_attributes("h1", &["id"])
-        .add_tag_attributes("h2", &["id"])
-        .add_tag_attributes("h3", &["id"])
-        .add_tag_attributes("h4", &["id"])
-        .add_tag_attributes("h5", &["id"])
-        .add_tag_attributes("h6", &["id"])
-        .add_tags(&["audio"])
-        .add_tag_attributes("video", &["src", "autoplay", "loop", "controls", "muted"])
-        .add_tags(&["video"])
-        .add_tag_attributes(
-            "video",
-            &["src", "autoplay", "loop", "controls", "muted", "width"],
-        )
-        .clean(&dirty_md)
-        .to_string()
+    html::push_html(&mut dirty_md, events.into_iter());
+
+    (sanitizer.builder().clean(&dirty_md).to_string(), toc)
 }
-}}
 
-#[server(endpoint = "get_note_as_html")]
-pub async fn get_note_as_html(path: String) -> Result<String, ServerFnError> {
-    // TODO: Write tests
+/// Returns the logged in user or sets the response status to unauthorized.
+fn authenticated_user() -> Result<auth::User, ServerFnError> {
     let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
         expect_context();
     let response: ResponseOptions = expect_context();
@@ -55,27 +302,1285 @@ pub async fn get_note_as_html(path: String) -> Result<String, ServerFnError> {
         return Err(ServerFnError::new("Authentication required"));
     }
 
-    let user = auth.current_user.expect("User was not authenticated");
-    let ln_settings: LazyNotesSettings = expect_context();
+    Ok(auth.current_user.expect("User was not authenticated"))
+}
+
+/// Builds the path of a file inside a user's notes directory.
+///
+/// Paths reaching outside of the notes directory, directly or through a
+/// symlink the configured policy forbids, are rejected.
+fn notes_path(
+    ln_settings: &LazyNotesSettings,
+    username: &str,
+    path: &str,
+) -> Result<PathBuf, ServerFnError> {
+    paths::resolve(&notes_root(ln_settings, username), path, ln_settings.symlink_policy)
+        .map_err(|_| ServerFnError::new("Invalid path"))
+}
+
+/// Path of a user's notes directory.
+pub fn notes_root(ln_settings: &LazyNotesSettings, username: &str) -> PathBuf {
+    PathBuf::from(format!("{}/{username}/notes", &ln_settings.data_dir))
+}
+
+/// Path of a user's resources directory.
+pub fn resources_path(ln_settings: &LazyNotesSettings, username: &str) -> PathBuf {
+    PathBuf::from(format!("{}/{username}/resources", &ln_settings.data_dir))
+}
+
+/// Reads a single level of a directory.
+///
+/// Hidden entries are skipped. Folders come first, then notes, then any other
+/// files, each sorted by name.
+pub(crate) fn read_directory(dir: &Path) -> std::io::Result<Vec<DirEntry>> {
+    let mut entries = Vec::new();
+
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // Follow symlinks so linked folders are listed as folders
+        let Ok(meta) = metadata(entry.path()) else {
+            continue;
+        };
+
+        let kind = if meta.is_dir() {
+            EntryKind::Directory
+        } else if name.ends_with(".md") {
+            EntryKind::Note
+        } else {
+            EntryKind::Resource
+        };
+
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .and_then(|duration| i64::try_from(duration.as_secs()).ok());
+
+        entries.push(DirEntry {
+            name,
+            kind,
+            modified,
+            size: if meta.is_dir() { 0 } else { meta.len() },
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        a.kind
+            .cmp(&b.kind)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+
+    Ok(entries)
+}
+
+/// Percent-encodes a path so it can be used inside an href.
+pub(crate) fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+/// Decodes a percent-encoded path, keeping invalid escapes as they are.
+pub(crate) fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Formats a byte count using binary units (e.g. `1.5 KiB`).
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    format!("{size:.1} {unit}")
+}
+
+/// Formats a unix timestamp in the server's local time.
+pub(crate) fn format_timestamp(secs: i64) -> String {
+    DateTime::from_timestamp(secs, 0)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Runs blocking work, like syncing the notes index or rendering notes, on a
+/// thread where it doesn't hold up other requests.
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ServerFnError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|_| ServerFnError::new("Internal error"))
+}
+
+/// Renders a note's markdown, taking out its frontmatter and resolving links.
+async fn render_note(
+    ln_settings: &LazyNotesSettings,
+    index: &NotesIndex,
+    username: &str,
+    markdown: String,
+) -> Result<RenderedNote, ServerFnError> {
+    let links = link_resolver(ln_settings, index, username).await?;
+    let index = index.clone();
+
+    run_blocking(move || render_markdown(&links, index.sanitizer(), &markdown)).await
+}
+
+/// Builds the resolver for the wiki links in the notes of a user.
+async fn link_resolver(
+    ln_settings: &LazyNotesSettings,
+    index: &NotesIndex,
+    username: &str,
+) -> Result<LinkResolver, ServerFnError> {
+    let notes_dir = notes_path(ln_settings, username, "")?;
+    let resources_dir = resources_path(ln_settings, username);
+    let index = index.clone();
+    let username = username.to_string();
+
+    run_blocking(move || index.link_resolver(&username, &notes_dir, &resources_dir)).await
+}
+
+/// Renders the markdown of a note along with its table of contents.
+fn render_markdown(
+    links: &LinkResolver,
+    sanitizer: &SanitizerSettings,
+    markdown: &str,
+) -> RenderedNote {
+    let version = content_version(markdown);
+    let (metadata, body) = split_frontmatter(markdown);
+    let (html, toc) = convert_to_html(body, links, sanitizer, markdown.len() - body.len());
+    RenderedNote {
+        html,
+        metadata,
+        toc,
+        version,
+    }
+}
+
+/// Whether a path goes through a hidden file or folder, like `.git`.
+pub fn is_hidden(path: &str) -> bool {
+    Path::new(path).components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+/// Normalizes the path of a note or folder that may be created, moved or
+/// deleted, returning it along with its location on disk.
+///
+/// The notes directory itself and hidden entries can't be managed. Symlinks
+/// are located themselves rather than where they point, so they are moved or
+/// deleted instead of their targets.
+fn managed_path(
+    ln_settings: &LazyNotesSettings,
+    username: &str,
+    path: &str,
+) -> Result<(String, PathBuf), ServerFnError> {
+    notes_path(ln_settings, username, path)?;
+    let path = Path::new(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if path.is_empty() {
+        return Err(ServerFnError::new("The notes folder itself can't be changed"));
+    }
+    if is_hidden(&path) {
+        return Err(ServerFnError::new("Hidden files and folders can't be changed"));
+    }
+
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+    let full_path = notes_path(ln_settings, username, parent)?.join(name);
+    Ok((path, full_path))
+}
+
+/// Path of a note that may be edited, mapping folders to their `index.md`.
+///
+/// Only markdown files outside of hidden folders can be edited.
+fn editable_note_path(
+    ln_settings: &LazyNotesSettings,
+    username: &str,
+    path: &str,
+) -> Result<PathBuf, ServerFnError> {
+    let mut note_path = notes_path(ln_settings, username, path)?;
+    if note_path.is_dir() {
+        note_path.push("index.md");
+    }
+
+    if is_hidden(path) || note_path.extension().map_or(true, |ext| ext != "md") {
+        return Err(ServerFnError::new("Only markdown notes can be edited"));
+    }
+
+    Ok(note_path)
+}
+
+/// Where the past versions of a user's notes are kept.
+///
+/// Git is run in blocking tasks, as it can take a while on large repositories.
+#[derive(Clone)]
+enum HistoryStore {
+    /// Commits of the git repository holding the notes directory
+    Git(PathBuf),
+    /// Snapshots written when notes are saved in the browser
+    Snapshots(PathBuf),
+}
+
+impl HistoryStore {
+    async fn new(ln_settings: &LazyNotesSettings, username: &str) -> Result<Self, ServerFnError> {
+        let notes_dir = notes_path(ln_settings, username, "")?;
+        let history_dir = history::snapshots_path(&ln_settings.data_dir, username);
+
+        run_blocking(move || match history::is_repository(&notes_dir) {
+            true => HistoryStore::Git(notes_dir),
+            false => HistoryStore::Snapshots(history_dir),
+        })
+        .await
+    }
+
+    async fn revisions(&self, path: &str, username: &str) -> Result<Vec<Revision>, ServerFnError> {
+        let (store, path, username) = (self.clone(), path.to_string(), username.to_string());
+
+        run_blocking(move || match store {
+            HistoryStore::Git(notes_dir) => history::log(&notes_dir, &path)
+                .map_err(|_| ServerFnError::new("Error reading the history")),
+            HistoryStore::Snapshots(history_dir) => {
+                Ok(history::list_snapshots(&history_dir, &path, &username))
+            }
+        })
+        .await?
+    }
+
+    /// Markdown of a note at a past revision.
+    async fn read(&self, path: &str, revision: &str) -> Result<String, ServerFnError> {
+        let (store, path, revision) = (self.clone(), path.to_string(), revision.to_string());
+        let content = run_blocking(move || match store {
+            HistoryStore::Git(notes_dir) => history::show(&notes_dir, &path, &revision),
+            HistoryStore::Snapshots(history_dir) => {
+                history::read_snapshot(&history_dir, &path, &revision)
+            }
+        })
+        .await?;
+
+        content.map_err(|e| match e.kind() {
+            ErrorKind::InvalidInput => ServerFnError::new("Invalid revision"),
+            ErrorKind::NotFound => ServerFnError::new("This revision of the note does not exist"),
+            _ => ServerFnError::new("Error reading the revision"),
+        })
+    }
+
+    /// Keeps the snapshots of a renamed note or folder with it. Git finds
+    /// renames by itself.
+    fn moved(&self, from: &str, to: &str) {
+        if let HistoryStore::Snapshots(history_dir) = self {
+            if let Err(e) = history::move_snapshots(history_dir, from, to) {
+                logging::warn!("Failed to move the snapshots of {from}: {e}");
+            }
+        }
+    }
+
+    /// Where snapshots are kept, for the trash to take them along with notes.
+    fn snapshots_dir(&self) -> Option<&Path> {
+        match self {
+            HistoryStore::Git(_) => None,
+            HistoryStore::Snapshots(history_dir) => Some(history_dir),
+        }
+    }
+
+    /// Snapshots a note saved in the browser, if its notes aren't kept in git.
+    ///
+    /// `previous` is the content that was replaced, snapshotted first in case
+    /// it was changed outside of Lazy Notes. The note is saved either way,
+    /// failures only cost history.
+    fn snapshot(
+        &self,
+        ln_settings: &LazyNotesSettings,
+        path: &str,
+        previous: Option<&str>,
+        content: &str,
+    ) {
+        let HistoryStore::Snapshots(history_dir) = self else {
+            return;
+        };
 
-    let ext = {
-        match &path[path.len() - 3..] != ".md" {
-            true => "/index.md".to_string(),
-            false => String::new(),
+        let retention = history::Retention {
+            max_count: ln_settings.history_max_snapshots,
+            max_age: i64::try_from(ln_settings.history_max_age_days.saturating_mul(86400))
+                .unwrap_or(i64::MAX),
+        };
+        let saved = previous
+            .map_or(Ok(()), |previous| {
+                history::save_snapshot(history_dir, path, previous, retention)
+            })
+            .and_then(|_| history::save_snapshot(history_dir, path, content, retention));
+        if let Err(e) = saved {
+            logging::warn!("Failed to snapshot {path}: {e}");
         }
+    }
+
+    /// Commits a note saved in the browser to git if enabled.
+    ///
+    /// Called once the write lock is released, so slow commits don't hold up
+    /// other saves. Failures only cost history.
+    async fn commit(&self, ln_settings: &LazyNotesSettings, path: &str, username: &str) {
+        let HistoryStore::Git(notes_dir) = self else {
+            return;
+        };
+        if !ln_settings.git_auto_commit {
+            return;
+        }
+
+        let (notes_dir, path, username) = (notes_dir.clone(), path.to_string(), username.to_string());
+        let committed = run_blocking(move || {
+            history::commit(&notes_dir, &path, &username).map_err(|e| (path, e))
+        })
+        .await;
+        if let Ok(Err((path, e))) = committed {
+            logging::warn!("Failed to commit {path}: {e}");
+        }
+    }
+}
+
+/// Path of a note relative to the notes directory, mapping folders to their `index.md`.
+fn relative_note_path(
+    ln_settings: &LazyNotesSettings,
+    username: &str,
+    path: &str,
+) -> Result<(String, PathBuf), ServerFnError> {
+    let notes_dir = notes_path(ln_settings, username, "")?;
+    let note_path = editable_note_path(ln_settings, username, path)?;
+    let path = note_path
+        .strip_prefix(&notes_dir)
+        .map_err(|_| ServerFnError::new("Invalid path"))?
+        .to_string_lossy()
+        .into_owned();
+
+    Ok((path, note_path))
+}
+
+/// Version of a note's content used to detect concurrent edits.
+pub(crate) fn content_version(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Writes a file by renaming a temporary file over it, so readers never see
+/// a partially written file. Missing parent folders are created.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let parent = path.parent().ok_or(ErrorKind::InvalidInput)?;
+    let name = path.file_name().ok_or(ErrorKind::InvalidInput)?;
+    create_dir_all(parent)?;
+
+    // Hidden so the temporary file never shows up in listings or the index
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let temp_path = parent.join(format!(
+        ".{}.{}-{nanos}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+
+    match written.and_then(|_| rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Renders the listing of a folder which has no `index.md`.
+///
+/// Names come straight from the filesystem so everything is escaped here.
+fn render_directory_listing(username: &str, path: &str, entries: &[DirEntry]) -> String {
+    let path = path.trim_matches('/');
+    let base = match path.is_empty() {
+        true => format!("/{username}/notes"),
+        false => format!("/{username}/notes/{}", encode_path(path)),
     };
 
-    // Get notes and process urls to reflect current user
-    let notes = read_to_string(format!(
-        "{}/{}/notes/{path}{ext}",
-        &ln_settings.data_dir, &user.username
-    ))
-    .map_err(|_| ServerFnError::new("Error reading markdown file"))?
-    .replace("](/resources", &format!("](/{}/resources", &user.username))
-    .replace(
-        "src=\"/resources",
-        &format!("src=\"/{}/resources", &user.username),
+    let mut listing = format!(
+        "<h1>{}/</h1><table class=\"dir_listing\"><thead><tr>\
+         <th>Name</th><th>Modified</th><th>Size</th>\
+         </tr></thead><tbody>",
+        clean_text(path)
     );
 
-    Ok(convert_to_html(&notes))
+    if !path.is_empty() {
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        listing.push_str(&format!(
+            "<tr class=\"directory\"><td><a href=\"/{username}/notes/{}\">..</a></td><td></td><td></td></tr>",
+            encode_path(parent)
+        ));
+    }
+
+    for entry in entries {
+        let name = clean_text(&entry.name);
+        let href = format!("{base}/{}", encode_path(&entry.name));
+        let modified = entry.modified.map(format_timestamp).unwrap_or_default();
+
+        // Other files are served by the resource route, like uploads
+        let (class, label, size) = match entry.kind {
+            EntryKind::Directory => (
+                "directory",
+                format!("<a href=\"{href}\">{name}/</a>"),
+                String::new(),
+            ),
+            EntryKind::Note => (
+                "note",
+                format!("<a href=\"{href}\">{name}</a>"),
+                format_size(entry.size),
+            ),
+            EntryKind::Resource => {
+                let file = match path.is_empty() {
+                    true => entry.name.clone(),
+                    false => format!("{path}/{}", entry.name),
+                };
+                let href = format!("/{username}/resources/{}", encode_path(&file));
+                ("resource", format!("<a href=\"{href}\">{name}</a>"), format_size(entry.size))
+            }
+        };
+
+        listing.push_str(&format!(
+            "<tr class=\"{class}\"><td>{label}</td><td>{modified}</td><td>{size}</td></tr>"
+        ));
+    }
+
+    listing.push_str("</tbody></table>");
+    listing
+}
+}}
+
+#[server(endpoint = "get_note_as_html")]
+pub async fn get_note_as_html(path: String) -> Result<RenderedNote, ServerFnError> {
+    // TODO: Write tests
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+    let cache: RenderCache = expect_context();
+
+    let note_path = notes_path(&ln_settings, &user.username, &path)?;
+
+    // Folders show their index.md, or a generated listing if they have none
+    let note_path = if note_path.is_dir() {
+        let index = note_path.join("index.md");
+        if !index.is_file() {
+            let entries = read_directory(&note_path)
+                .map_err(|_| ServerFnError::new("Error reading directory"))?;
+            let html = render_directory_listing(&user.username, &path, &entries);
+            return Ok(RenderedNote {
+                html,
+                ..Default::default()
+            });
+        }
+        index
+    } else {
+        note_path
+    };
+
+    let file =
+        metadata(&note_path).map_err(|_| ServerFnError::new("Error reading markdown file"))?;
+
+    // Links render differently once the notes they point to are created or removed
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    let resources_dir = resources_path(&ln_settings, &user.username);
+    let generation = {
+        let index = index.clone();
+        let username = user.username.clone();
+        run_blocking(move || index.generation(&username, &notes_dir, &resources_dir)).await?
+    };
+
+    let stamp = RenderStamp::new(&file, generation);
+    cache
+        .get_or_render(&note_path, stamp, || async {
+            let notes = read_to_string(&note_path)
+                .map_err(|_| ServerFnError::new("Error reading markdown file"))?;
+            render_note(&ln_settings, &index, &user.username, notes).await
+        })
+        .await
+}
+
+/// Hit and miss counts of the render cache since the server started.
+#[server(endpoint = "get_render_cache_stats")]
+pub async fn get_render_cache_stats() -> Result<CacheStats, ServerFnError> {
+    authenticated_user()?;
+    let cache: RenderCache = expect_context();
+
+    Ok(cache.stats())
+}
+
+/// Gets the markdown of a note for editing.
+///
+/// Notes which do not exist yet are returned empty with an empty version.
+#[server(endpoint = "get_note_source")]
+pub async fn get_note_source(path: String) -> Result<NoteSource, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let note_path = editable_note_path(&ln_settings, &user.username, &path)?;
+    match read_to_string(&note_path) {
+        Ok(content) => Ok(NoteSource {
+            version: content_version(&content),
+            content,
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(NoteSource::default()),
+        Err(_) => Err(ServerFnError::new("Error reading markdown file")),
+    }
+}
+
+/// Renders unsaved markdown the same way as a saved note.
+#[server(endpoint = "preview_note")]
+pub async fn preview_note(content: String) -> Result<RenderedNote, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    render_note(&ln_settings, &index, &user.username, content).await
+}
+
+/// Saves a note, returning its new version.
+///
+/// `version` must be the version the edit started from, or empty when creating
+/// a note. Saving is refused if the note changed on disk in the meantime.
+#[server(endpoint = "save_note")]
+pub async fn save_note(
+    path: String,
+    content: String,
+    version: String,
+) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+    let response: ResponseOptions = expect_context();
+
+    let (path, note_path) = relative_note_path(&ln_settings, &user.username, &path)?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+
+    {
+        // Hold the lock between checking the version and writing so saves can't interleave
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let previous = match read_to_string(&note_path) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(_) => return Err(ServerFnError::new("Error reading markdown file")),
+        };
+        let current = previous.as_deref().map(content_version);
+
+        if current.as_deref().unwrap_or_default() != version {
+            response.set_status(StatusCode::CONFLICT);
+            return Err(ServerFnError::new(match current {
+                Some(_) => "The note was changed by someone else, reload it before saving",
+                None => "The note was deleted by someone else",
+            }));
+        }
+
+        write_atomically(&note_path, content.as_bytes())
+            .map_err(|_| ServerFnError::new("Error saving note"))?;
+        index.invalidate(&user.username);
+        history.snapshot(&ln_settings, &path, previous.as_deref(), &content);
+    }
+    history.commit(&ln_settings, &path, &user.username).await;
+
+    Ok(content_version(&content))
+}
+
+/// Checks or unchecks a task list item of a note, returning the note's new
+/// version.
+///
+/// The item is found by the offset of its `[ ]` marker in the note, which is
+/// only trusted if the note is still at the `version` it was rendered from.
+#[server(endpoint = "toggle_task")]
+pub async fn toggle_task(
+    path: String,
+    offset: usize,
+    checked: bool,
+    version: String,
+) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+    let response: ResponseOptions = expect_context();
+
+    let (path, note_path) = relative_note_path(&ln_settings, &user.username, &path)?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+
+    let content = {
+        // Hold the lock between checking the version and writing so saves can't interleave
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let previous = match read_to_string(&note_path) {
+            Ok(previous) => previous,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                response.set_status(StatusCode::CONFLICT);
+                return Err(ServerFnError::new("The note was deleted by someone else"));
+            }
+            Err(_) => return Err(ServerFnError::new("Error reading markdown file")),
+        };
+
+        if content_version(&previous) != version {
+            response.set_status(StatusCode::CONFLICT);
+            return Err(ServerFnError::new(
+                "The note was changed by someone else, reload it before checking tasks",
+            ));
+        }
+
+        let Some(content) = set_task(&previous, offset, checked) else {
+            response.set_status(StatusCode::BAD_REQUEST);
+            return Err(ServerFnError::new("There is no task at this position"));
+        };
+
+        write_atomically(&note_path, content.as_bytes())
+            .map_err(|_| ServerFnError::new("Error saving note"))?;
+        index.invalidate(&user.username);
+        history.snapshot(&ln_settings, &path, Some(previous.as_str()), &content);
+        content
+    };
+    history.commit(&ln_settings, &path, &user.username).await;
+
+    Ok(content_version(&content))
+}
+
+/// Creates a note with a title heading, returning its path.
+///
+/// `.md` is appended to the path if it is missing.
+#[server(endpoint = "create_note")]
+pub async fn create_note(path: String) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let (mut path, _) = managed_path(&ln_settings, &user.username, &path)?;
+    if !path.ends_with(".md") {
+        path.push_str(".md");
+    }
+    let note_path = notes_path(&ln_settings, &user.username, &path)?;
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if note_path.exists() {
+        return Err(ServerFnError::new("A note with this name already exists"));
+    }
+
+    let name = path.rsplit('/').next().unwrap_or(&path).trim_end_matches(".md");
+    write_atomically(&note_path, format!("# {name}\n").as_bytes())
+        .map_err(|_| ServerFnError::new("Error creating note"))?;
+    index.invalidate(&user.username);
+
+    Ok(path)
+}
+
+/// Creates a folder along with any missing parents, returning its path.
+#[server(endpoint = "create_directory")]
+pub async fn create_directory(path: String) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let (path, dir) = managed_path(&ln_settings, &user.username, &path)?;
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if dir.exists() {
+        return Err(ServerFnError::new("A file or folder with this name already exists"));
+    }
+
+    create_dir_all(&dir).map_err(|_| ServerFnError::new("Error creating folder"))?;
+    Ok(path)
+}
+
+/// Renames or moves a note or folder, returning its new path.
+///
+/// With `update_links`, links to the moved notes are rewritten in every note,
+/// as are relative links inside the moved notes. Notes that can't be updated
+/// are skipped and listed, as the entry has been moved by then.
+#[server(endpoint = "rename_entry")]
+pub async fn rename_entry(
+    from: String,
+    to: String,
+    update_links: bool,
+) -> Result<RenamedEntry, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let (from, from_path) = managed_path(&ln_settings, &user.username, &from)?;
+    let (mut to, _) = managed_path(&ln_settings, &user.username, &to)?;
+
+    // Notes stay notes even if the extension was left out
+    if from.ends_with(".md") && from_path.is_file() && !to.ends_with(".md") {
+        to.push_str(".md");
+    }
+    let to_path = notes_path(&ln_settings, &user.username, &to)?;
+
+    if to.starts_with(&format!("{from}/")) {
+        return Err(ServerFnError::new("A folder can't be moved into itself"));
+    }
+
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+    let username = user.username;
+
+    // Syncing the index and rewriting every note can take a while
+    run_blocking(move || {
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !from_path.exists() {
+            return Err(ServerFnError::new("The note or folder no longer exists"));
+        }
+        if to_path.exists() {
+            return Err(ServerFnError::new("A file or folder with this name already exists"));
+        }
+
+        // Snapshot the notes before moving anything to know where links pointed
+        let notes: Vec<String> = index.with_user(&username, &notes_dir, |index| {
+            index.notes.keys().cloned().collect()
+        });
+        let moved: HashMap<String, String> = notes
+            .iter()
+            .filter_map(|note| match note == &from {
+                true => Some((note.clone(), to.clone())),
+                false => note
+                    .strip_prefix(&format!("{from}/"))
+                    .map(|rest| (note.clone(), format!("{to}/{rest}"))),
+            })
+            .collect();
+
+        if let Some(parent) = to_path.parent() {
+            create_dir_all(parent).map_err(|_| ServerFnError::new("Error creating folder"))?;
+        }
+        rename(&from_path, &to_path).map_err(|_| ServerFnError::new("Error renaming"))?;
+        index.invalidate(&username);
+        history.moved(&from, &to);
+
+        let mut not_updated = Vec::new();
+        if update_links && !moved.is_empty() {
+            let before = LinkResolver::new(&username, notes.iter().cloned(), []);
+            let after = LinkResolver::new(
+                &username,
+                notes.iter().map(|note| moved.get(note).unwrap_or(note).clone()),
+                [],
+            );
+
+            for note in &notes {
+                let new_path = moved.get(note).unwrap_or(note);
+                let path = notes_dir.join(new_path);
+                let Ok(markdown) = read_to_string(&path) else {
+                    not_updated.push(new_path.clone());
+                    continue;
+                };
+
+                let rewritten = rewrite_links(&username, note, &markdown, &moved, &before, &after);
+                let written = rewritten.map_or(Ok(()), |rewritten| {
+                    write_atomically(&path, rewritten.as_bytes())
+                });
+                if let Err(e) = written {
+                    logging::warn!("Failed to update the links in {new_path}: {e}");
+                    not_updated.push(new_path.clone());
+                }
+            }
+        }
+
+        Ok(RenamedEntry {
+            path: to,
+            not_updated,
+        })
+    })
+    .await?
+}
+
+/// Moves a note or folder to the trash, returning the path of its parent folder.
+#[server(endpoint = "delete_entry")]
+pub async fn delete_entry(path: String) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let (path, full_path) = managed_path(&ln_settings, &user.username, &path)?;
+    if symlink_metadata(&full_path).is_err() {
+        return Err(ServerFnError::new("Nothing exists at this path"));
+    }
+
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let trash_dir = trash::trash_path(&ln_settings.data_dir, &user.username);
+    trash::move_to_trash(&trash_dir, &path, &full_path, history.snapshots_dir())
+        .map_err(|_| ServerFnError::new("Error moving to the trash"))?;
+    index.invalidate(&user.username);
+
+    Ok(path.rsplit_once('/').map_or("", |(parent, _)| parent).to_string())
+}
+
+/// Lists the user's trash, most recently deleted first.
+#[server(endpoint = "list_trash")]
+pub async fn list_trash() -> Result<Vec<TrashEntry>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let trash_dir = trash::trash_path(&ln_settings.data_dir, &user.username);
+    Ok(trash::list_trash(&trash_dir))
+}
+
+/// Restores a trashed note or folder, returning its path.
+#[server(endpoint = "restore_trash_entry")]
+pub async fn restore_trash_entry(id: String) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let trash_dir = trash::trash_path(&ln_settings.data_dir, &user.username);
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let policy = ln_settings.symlink_policy;
+    let restored = trash::restore(&trash_dir, &notes_dir, &id, policy, history.snapshots_dir())
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => {
+                ServerFnError::new("Something already exists where this was deleted from")
+            }
+            _ => ServerFnError::new("Error restoring from the trash"),
+        })?;
+    index.invalidate(&user.username);
+
+    Ok(restored)
+}
+
+/// Permanently deletes a trashed note or folder.
+#[server(endpoint = "purge_trash_entry")]
+pub async fn purge_trash_entry(id: String) -> Result<(), ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let trash_dir = trash::trash_path(&ln_settings.data_dir, &user.username);
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    trash::purge(&trash_dir, &id).map_err(|_| ServerFnError::new("Error purging from the trash"))
+}
+
+/// Lists a single level of the user's notes directory.
+#[server(endpoint = "list_directory")]
+pub async fn list_directory(path: String) -> Result<Vec<DirEntry>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let dir = notes_path(&ln_settings, &user.username, &path)?;
+    read_directory(&dir).map_err(|_| ServerFnError::new("Error reading directory"))
+}
+
+/// Searches every note of the user.
+#[server(endpoint = "search_notes")]
+pub async fn search_notes(query: String) -> Result<Vec<SearchHit>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    run_blocking(move || index.search(&user.username, &notes_dir, &query)).await
+}
+
+/// Lists the notes linking to the note at `path`.
+#[server(endpoint = "get_backlinks")]
+pub async fn get_backlinks(path: String) -> Result<Vec<Backlink>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    // Folders are linked to through their index.md
+    let mut path = path.trim_matches('/').to_string();
+    if notes_path(&ln_settings, &user.username, &path)?.is_dir() {
+        path = match path.is_empty() {
+            true => "index.md".to_string(),
+            false => format!("{path}/index.md"),
+        };
+    }
+
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    run_blocking(move || index.backlinks(&user.username, &notes_dir, &path)).await
+}
+
+/// Lists every tag used in the user's notes with the number of notes carrying it.
+#[server(endpoint = "list_tags")]
+pub async fn list_tags() -> Result<Vec<TagCount>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    run_blocking(move || index.tags(&user.username, &notes_dir)).await
+}
+
+/// Lists the notes carrying a tag or one of its nested tags.
+#[server(endpoint = "get_tagged_notes")]
+pub async fn get_tagged_notes(tag: String) -> Result<Vec<TaggedNote>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let notes_dir = notes_path(&ln_settings, &user.username, "")?;
+    run_blocking(move || index.tagged_notes(&user.username, &notes_dir, &tag)).await
+}
+
+/// Lists the past versions of a note, newest first.
+///
+/// These are the commits changing the note if the notes are kept in git, or
+/// the snapshots taken when saving it in the browser otherwise.
+#[server(endpoint = "get_note_history")]
+pub async fn get_note_history(path: String) -> Result<Vec<Revision>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let (path, _) = relative_note_path(&ln_settings, &user.username, &path)?;
+    HistoryStore::new(&ln_settings, &user.username)
+        .await?
+        .revisions(&path, &user.username)
+        .await
+}
+
+/// Renders a note as it was at a past revision.
+#[server(endpoint = "get_note_revision")]
+pub async fn get_note_revision(
+    path: String,
+    revision: String,
+) -> Result<RenderedNote, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+
+    let (path, _) = relative_note_path(&ln_settings, &user.username, &path)?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+    let markdown = history.read(&path, &revision).await?;
+
+    render_note(&ln_settings, &index, &user.username, markdown).await
+}
+
+/// Diffs two revisions of a note. An empty `to` compares against the note as it is now.
+#[server(endpoint = "get_note_diff")]
+pub async fn get_note_diff(
+    path: String,
+    from: String,
+    to: String,
+) -> Result<Vec<DiffLine>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    let (path, note_path) = relative_note_path(&ln_settings, &user.username, &path)?;
+    let store = HistoryStore::new(&ln_settings, &user.username).await?;
+    let old = store.read(&path, &from).await?;
+    let new = match to.is_empty() {
+        true => read_to_string(note_path)
+            .map_err(|_| ServerFnError::new("Error reading markdown file"))?,
+        false => store.read(&path, &to).await?,
+    };
+
+    run_blocking(move || history::diff_lines(&old, &new)).await
+}
+
+/// Replaces a note with one of its past revisions, returning the note's new version.
+///
+/// `version` must be the version of the note the revision replaces, like when
+/// saving. The replaced content is recorded like any save, so restoring can be
+/// undone unless the notes are in git without auto-committing.
+#[server(endpoint = "restore_note_revision")]
+pub async fn restore_note_revision(
+    path: String,
+    revision: String,
+    version: String,
+) -> Result<String, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+    let index: NotesIndex = expect_context();
+    let response: ResponseOptions = expect_context();
+
+    let (path, note_path) = relative_note_path(&ln_settings, &user.username, &path)?;
+    let history = HistoryStore::new(&ln_settings, &user.username).await?;
+    let content = history.read(&path, &revision).await?;
+
+    {
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let previous = match read_to_string(&note_path) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(_) => return Err(ServerFnError::new("Error reading markdown file")),
+        };
+
+        if previous.as_deref().map(content_version).unwrap_or_default() != version {
+            response.set_status(StatusCode::CONFLICT);
+            return Err(ServerFnError::new(
+                "The note was changed by someone else, reload it before restoring",
+            ));
+        }
+
+        write_atomically(&note_path, content.as_bytes())
+            .map_err(|_| ServerFnError::new("Error restoring note"))?;
+        index.invalidate(&user.username);
+        history.snapshot(&ln_settings, &path, previous.as_deref(), &content);
+    }
+    history.commit(&ln_settings, &path, &user.username).await;
+
+    Ok(content_version(&content))
+}
+
+/// Lists the files uploaded to the user's resources directory.
+#[server(endpoint = "list_resources")]
+pub async fn list_resources() -> Result<Vec<ResourceFile>, ServerFnError> {
+    let user = authenticated_user()?;
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    Ok(uploads::list_resources(&resources_path(
+        &ln_settings,
+        &user.username,
+    )))
+}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::api::{
+        content_version, convert_to_html, decode_path, encode_path, format_size, is_hidden,
+        read_directory, render_directory_listing, render_markdown, write_atomically, DirEntry,
+        EntryKind,
+    };
+    use crate::markdown::{set_task, task_offsets, LinkResolver};
+    use crate::sanitizer::SanitizerSettings;
+    use std::fs::{create_dir_all, read_dir, read_to_string, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn directory_listing_order() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(dir.join("zeta")).unwrap();
+        create_dir_all(dir.join("Alpha")).unwrap();
+        write(dir.join("b.md"), "# B").unwrap();
+        write(dir.join("A.md"), "# A").unwrap();
+        write(dir.join("image.png"), [0u8; 16]).unwrap();
+        write(dir.join(".hidden.md"), "").unwrap();
+
+        let entries = read_directory(&dir).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.kind)).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                ("Alpha", EntryKind::Directory),
+                ("zeta", EntryKind::Directory),
+                ("A.md", EntryKind::Note),
+                ("b.md", EntryKind::Note),
+                ("image.png", EntryKind::Resource),
+            ]
+        );
+        assert_eq!(entries[4].size, 16);
+    }
+
+    #[test]
+    fn directory_listing_links() {
+        let entries = [
+            DirEntry {
+                name: "Plan.md".to_string(),
+                kind: EntryKind::Note,
+                modified: None,
+                size: 10,
+            },
+            DirEntry {
+                name: "My Chart.png".to_string(),
+                kind: EntryKind::Resource,
+                modified: None,
+                size: 20,
+            },
+        ];
+        let html = render_directory_listing("bob", "projects/", &entries);
+
+        assert!(html.contains("<a href=\"/bob/notes/projects/Plan.md\">Plan.md</a>"));
+        let chart = "<a href=\"/bob/resources/projects/My%20Chart.png\">My&#32;Chart.png</a>";
+        assert!(html.contains(chart));
+    }
+
+    #[test]
+    fn highlighting_survives_sanitization() {
+        let (html, _) = convert_to_html(
+            "```rust {1}\nlet x = 1;\n```\n\n<span class=\"evil hl-fake\">a</span><span class=\"evil\">b</span>",
+            &LinkResolver::default(),
+            &SanitizerSettings::default(),
+            0,
+        );
+
+        assert!(html.contains("<pre class=\"highlight\"><code><span class=\"hl-line hl-marked\">"));
+        assert!(html.contains("hl-keyword"));
+        assert!(html.contains("<span class=\"hl-fake\">a</span><span>b</span>"));
+    }
+
+    #[test]
+    fn math_survives_sanitization() {
+        let (html, _) = convert_to_html(
+            "$x^2$",
+            &LinkResolver::default(),
+            &SanitizerSettings::default(),
+            0,
+        );
+
+        assert!(html.contains("<math display=\"inline\">"));
+        assert!(html.contains("<msup><mi>x</mi><mn>2</mn></msup>"));
+    }
+
+    #[test]
+    fn callouts_survive_sanitization() {
+        let (html, _) = convert_to_html(
+            "> [!warning]- Before upgrading\n> Take a backup.",
+            &LinkResolver::default(),
+            &SanitizerSettings::default(),
+            0,
+        );
+
+        assert_eq!(
+            html,
+            "<details class=\"callout\" data-callout=\"warning\"><summary class=\"callout-title\">\
+             Before upgrading</summary><div class=\"callout-content\">\n<p>Take a backup.</p>\n</div></details>\n"
+        );
+    }
+
+    #[test]
+    fn tasks_survive_sanitization() {
+        let (html, _) = convert_to_html(
+            "- [x] Done\n\n<input type=\"text\" class=\"task\" onclick=\"alert(1)\">",
+            &LinkResolver::default(),
+            &SanitizerSettings::default(),
+            1,
+        );
+
+        assert!(html.contains(
+            "<li><input type=\"checkbox\" class=\"task\" data-offset=\"3\" checked=\"\" disabled=\"\">"
+        ));
+        assert!(html.contains("<input class=\"task\">"));
+    }
+
+    #[test]
+    fn task_offsets_match_rendering() {
+        let note = "---\ntitle: Maths\n---\n$$\n- [ ] x\n$$\n\nPay $5 or $y$:\n\n\
+                    - [ ] Rent $r_1$\n- [x] Tax\n";
+        let links = LinkResolver::default();
+        let rendered = render_markdown(&links, &SanitizerSettings::default(), note);
+
+        let offsets = task_offsets(note);
+        assert_eq!(offsets.len(), 2);
+        for offset in &offsets {
+            assert!(rendered.html.contains(&format!("data-offset=\"{offset}\"")));
+        }
+
+        let toggled = set_task(note, offsets[1], false).unwrap();
+        assert!(toggled.ends_with("- [ ] Tax\n"));
+    }
+
+    #[test]
+    fn resource_urls() {
+        let links = LinkResolver::new("bob", Vec::new(), Vec::new());
+        let rendered = render_markdown(
+            &links,
+            &SanitizerSettings::default(),
+            "[pdf](/resources/a.pdf) ![](/resources/b.png) <img src=\"/resources/c.png\">\n\n\
+             `[code](/resources/d)`",
+        );
+
+        assert!(rendered.html.contains("href=\"/bob/resources/a.pdf\""));
+        assert!(rendered.html.contains("src=\"/bob/resources/b.png\""));
+        assert!(rendered.html.contains("src=\"/bob/resources/c.png\""));
+        assert!(rendered.html.contains("<code>[code](/resources/d)</code>"));
+    }
+
+    #[test]
+    fn size_formatting() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(10 * 1024 * 1024), "10.0 MiB");
+    }
+
+    #[test]
+    fn path_encoding() {
+        assert_eq!(encode_path("notes/index.md"), "notes/index.md");
+        assert_eq!(encode_path("my notes/#1?.md"), "my%20notes/%231%3F.md");
+        assert_eq!(decode_path("my%20notes/%231%3F.md"), "my notes/#1?.md");
+        assert_eq!(decode_path("100%.md"), "100%.md");
+    }
+
+    #[test]
+    fn atomic_writes() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let path = dir.join("folder/note.md");
+
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "second");
+
+        // No temporary files are left behind
+        assert_eq!(read_dir(dir.join("folder")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn content_versions() {
+        assert_eq!(content_version("# Note"), content_version("# Note"));
+        assert_ne!(content_version("# Note"), content_version("# Note\n"));
+        assert_eq!(content_version("").len(), 64);
+    }
+
+    #[test]
+    fn hidden_paths() {
+        assert!(is_hidden(".trash/note.md"));
+        assert!(is_hidden("notes/.git"));
+        assert!(!is_hidden("./notes/note.md"));
+        assert!(!is_hidden("projects/v1.2/notes.md"));
+    }
 }
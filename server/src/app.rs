@@ -3,27 +3,22 @@ use cfg_if::cfg_if;
 // Tell rustc that components use ssr with islands enabled
 cfg_if! { if #[cfg(feature = "ssr")] {
 use axum_session_auth::{AuthSession, SessionSurrealPool};
-use ammonia::is_html;
-use crate::api::get_note_as_html;
+use crate::api::{
+    encode_path, format_size, format_timestamp, get_backlinks, get_note_as_html, get_note_diff,
+    get_note_history, get_note_revision, get_note_source, get_tagged_notes, list_directory,
+    list_resources, list_tags, list_trash, search_notes, Backlink, DiffLine, DirEntry, EntryKind,
+    ResourceFile, Revision, SearchHit, TagCount, TaggedNote, TocHeading, TrashEntry,
+};
 use crate::auth;
 use crate::settings::LazyNotesSettings;
-use html5ever::{
-    ATOM_LOCALNAME__68_31 as TOKEN_H1,
-    ATOM_LOCALNAME__68_32 as TOKEN_H2,
-    ATOM_LOCALNAME__68_33 as TOKEN_H3,
-    ATOM_LOCALNAME__68_34 as TOKEN_H4,
-    ATOM_LOCALNAME__68_35 as TOKEN_H5,
-    ATOM_LOCALNAME__68_36 as TOKEN_H6,
-    ATOM_LOCALNAME__63_6C_61_73_73 as TOKEN_CLASS,
-    ATOM_LOCALNAME__69_64 as TOKEN_ID,
-    tendril::StrTendril,
-    tokenizer::{BufferQueue, Token, Tokenizer, TokenSink, TokenSinkResult, Tag, TagKind},
-};
+use crate::uploads;
 use http::StatusCode;
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 use leptos_axum::ResponseOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use surrealdb::{engine::remote::ws::Client, Surreal};
 
 #[derive(Clone, Params, PartialEq)]
@@ -32,80 +27,26 @@ struct NotesParams {
     path: String,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct TocHeading {
-    level: u8,
-    class: Option<Box<str>>,
-    id: Option<Box<str>>,
-    text: Option<Box<str>>
-}
-
-impl TocHeading {
-    fn set_text(&mut self, text: &str) {
-        self.text = Some(Box::from(text));
-    }
-}
-
-struct TocSink {
-    headings: Vec<TocHeading>
-}
-
-impl TokenSink for TocSink {
-    type Handle = ();
-
-    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<Self::Handle> {
-        match token {
-            Token::TagToken(Tag {
-                kind: TagKind::StartTag,
-                name,
-                self_closing: false,
-                attrs
-            }) => {
-                if [TOKEN_H1, TOKEN_H2, TOKEN_H3, TOKEN_H4, TOKEN_H5, TOKEN_H6].contains(&name) {
-                    let level = match name {
-                        TOKEN_H1 => 1,
-                        TOKEN_H2 => 2,
-                        TOKEN_H3 => 3,
-                        TOKEN_H4 => 4,
-                        TOKEN_H5 => 5,
-                        TOKEN_H6 => 6,
-                        _ => 0,  // Impossible
-                    };
-
-                    let class = attrs
-                        .iter()
-                        .find(|a| a.name.local == TOKEN_CLASS)
-                        .map(|a| Some(Box::from(a.value.to_string())))
-                        .unwrap_or_else(|| None);
-
-                    let id = attrs
-                        .iter()
-                        .find(|a| a.name.local == TOKEN_ID)
-                        .map(|a| Some(Box::from(a.value.to_string())))
-                        .unwrap_or_else(|| None);
-
-                    self.headings.push(TocHeading {
-                        level,
-                        class,
-                        id,
-                        text: None
-                    });
-                }
-            },
-            Token::CharacterTokens(string) => {
-                if let Some(heading) = self.headings.last_mut() {
-                    if heading.text.is_some() {
-                        return TokenSinkResult::Continue;
-                    }
+#[derive(Clone, Params, PartialEq)]
+struct UserParams {
+    user: String,
+}
 
-                    heading.set_text(&string);
-                }
-            }
-            _ => {},
-        };
+#[derive(Clone, Params, PartialEq)]
+struct TagParams {
+    user: String,
+    tag: String,
+}
 
-        TokenSinkResult::Continue
-    }
+/// The loaded levels of a user's notes directory, keyed by folder path.
+///
+/// Only the folders leading to the current note are loaded up front, the rest
+/// of the tree is fetched by the browser as folders are expanded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileTree {
+    username: String,
+    current: String,
+    levels: HashMap<String, Vec<DirEntry>>,
 }
 
 #[component]
@@ -127,6 +68,13 @@ pub fn App() -> impl IntoView {
                     <Route path="/home" view=HomePage/>
                     <Route path="/signup" view=Signup/>
                     <Route path="/login" view=Login/>
+                    <Route path="/:user/search" view=Search ssr=SsrMode::PartiallyBlocked/>
+                    <Route path="/:user/uploads" view=Uploads ssr=SsrMode::PartiallyBlocked/>
+                    <Route path="/:user/trash" view=Trash ssr=SsrMode::PartiallyBlocked/>
+                    <Route path="/:user/tags" view=|| view! { <Outlet/> }>
+                        <Route path="" view=Tags ssr=SsrMode::PartiallyBlocked/>
+                        <Route path="*tag" view=Tag ssr=SsrMode::PartiallyBlocked/>
+                    </Route>
                     <Route path="/:user/notes" view=|| view! { <Outlet/> }>
                         <Route path="" view=|| leptos_axum::redirect("notes/index.md")/>
                         <Route
@@ -159,60 +107,328 @@ pub fn Logo() -> impl IntoView {
 }
 
 #[component]
-pub fn TocSidebar(toc: Vec<TocHeading>) -> impl IntoView {
-    if toc.is_empty() {
+pub fn TocSidebar(
+    toc: Vec<TocHeading>,
+    #[prop(default = None)]
+    file_tree: Option<FileTree>
+) -> impl IntoView {
+    // Open the file tree when there is nothing to show in the TOC
+    let show_files = toc.is_empty();
+
+    let Some(file_tree) = file_tree else {
         return view! {
             <nav id="toc_wrapper">
-                <ul id="toc"/>
+                <TocPanel toc=toc/>
             </nav>
         }
+    };
+
+    view! {
+        <nav id="toc_wrapper">
+            <input type="radio" name="sidebar_panel" id="toc_panel" checked=!show_files/>
+            <input type="radio" name="sidebar_panel" id="files_panel" checked=show_files/>
+            <section class="sidebar_tabs">
+                <label for="toc_panel">"Contents"</label>
+                <label for="files_panel">"Files"</label>
+            </section>
+            <TocPanel toc=toc/>
+            <FileTreePanel tree=file_tree/>
+        </nav>
     }
+}
 
-    let mut toc_tree = String::new();
-    let mut last_heading: u8 = toc[0].level;
-    let mut nest_count: u8 = 0;
+#[component]
+pub fn TocPanel(toc: Vec<TocHeading>) -> impl IntoView {
+    view! {
+        // Highlight the heading in view and remember collapsed sections
+        <Script>
+        "
+            window.addEventListener('DOMContentLoaded', () => {
+                const nav = document.getElementById('toc_wrapper');
+                const toc = document.getElementById('toc');
+                if (!nav || !toc) {
+                    return;
+                }
 
-    // Construct TOC list manually (safe because classes and ids are sanitized)
-    for heading in toc {
-        let id = heading.id.unwrap_or_else(|| "".into());
-        let text = heading.text.clone().unwrap_or_else(|| "".into());
+                const key = 'toc_collapsed:' + location.pathname;
+                let active = null;
+
+                const setCollapsed = (item, collapse) => {
+                    item.classList.toggle('collapsed', collapse);
+                    item.querySelector(':scope > .toc_toggle')
+                        .setAttribute('aria-expanded', String(!collapse));
+                };
+
+                const update = () => {
+                    // The current heading is the last one scrolled past the top quarter
+                    const limit = window.innerHeight / 4;
+                    let current = null;
+                    for (const heading of document.querySelectorAll(
+                        '#notes :is(h1, h2, h3, h4, h5, h6)[id]')) {
+                        if (heading.getBoundingClientRect().top > limit) {
+                            break;
+                        }
+                        current = heading;
+                    }
 
-        if heading.level > last_heading {
-            toc_tree.push_str(&format!("<li><ul><li><a href=#{id}>{text}</a></li>"));
-            nest_count += 1;
-        } else if heading.level < last_heading && nest_count > 0 {
-            toc_tree.push_str(&format!("</ul></li><li><a href=#{id}>{text}</a></li>"));
-            nest_count -= 1;
-        } else {
-            toc_tree.push_str(&format!("<li><a href=#{id}>{text}</a></li>"));
-        }
+                    let link = current && [...toc.querySelectorAll('a')]
+                        .find(a => a.getAttribute('href') === '#' + current.id);
 
-        last_heading = heading.level;
-    }
+                    // Headings in collapsed sections highlight the section instead
+                    let item = link && link.parentElement.parentElement.closest('li.collapsed');
+                    while (item) {
+                        link = item.querySelector(':scope > a');
+                        item = item.parentElement.closest('li.collapsed');
+                    }
+
+                    if (link === active) {
+                        return;
+                    }
+                    if (active) {
+                        active.classList.remove('active');
+                    }
+                    active = link;
+                    if (!link) {
+                        return;
+                    }
+                    link.classList.add('active');
+
+                    // Keep the entry in view without scrolling the page
+                    const box = link.getBoundingClientRect();
+                    const bounds = nav.getBoundingClientRect();
+                    if (box.top < bounds.top || box.bottom > bounds.bottom) {
+                        nav.scrollTop += box.top - bounds.top - bounds.height / 2;
+                    }
+                };
+
+                const restore = () => {
+                    let collapsed = [];
+                    try {
+                        collapsed = JSON.parse(localStorage.getItem(key)) || [];
+                    } catch {}
 
-    // Close any unclosed nested lists
-    while nest_count > 0 {
-        toc_tree.push_str("</ul></li>");
-        nest_count -= 1;
+                    for (const item of toc.querySelectorAll('li.toc_branch')) {
+                        setCollapsed(item, collapsed.includes(item.dataset.heading));
+                    }
+                };
+                restore();
+
+                toc.addEventListener('click', ev => {
+                    const toggle = ev.target.closest('.toc_toggle');
+                    if (!toggle) {
+                        return;
+                    }
+
+                    const item = toggle.parentElement;
+                    setCollapsed(item, !item.classList.contains('collapsed'));
+
+                    const ids = [...toc.querySelectorAll('li.collapsed')]
+                        .map(item => item.dataset.heading);
+                    try {
+                        if (ids.length) {
+                            localStorage.setItem(key, JSON.stringify(ids));
+                        } else {
+                            localStorage.removeItem(key);
+                        }
+                    } catch {}
+                    update();
+                });
+
+                let frame = null;
+                window.addEventListener('scroll', () => {
+                    cancelAnimationFrame(frame);
+                    frame = requestAnimationFrame(update);
+                }, { passive: true });
+
+                // Notes reloaded after a change on disk bring a new list of headings
+                document.addEventListener('note_reloaded', () => {
+                    active = null;
+                    restore();
+                    update();
+                });
+                update();
+            });
+        "
+        </Script>
+        <ul id="toc"><TocList headings=toc/></ul>
     }
+}
 
+#[component]
+pub fn FileTreePanel(tree: FileTree) -> impl IntoView {
     view! {
-        <nav id="toc_wrapper">
-            <ul id="toc" inner_html=toc_tree/>
-        </nav>
+        // Fetch the contents of folders the first time they are expanded
+        <Script>
+        "
+            document.addEventListener('toggle', async ev => {
+                const details = ev.target;
+                const tree = details.closest && details.closest('#file_tree');
+                if (!tree || !details.open || details.dataset.loaded) {
+                    return;
+                }
+
+                details.dataset.loaded = 'true';
+                const res = await fetch('/api/list_directory', {
+                    method: 'POST',
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/x-www-form-urlencoded',
+                    },
+                    body: new URLSearchParams({ path: details.dataset.path }),
+                });
+
+                if (!res.ok) {
+                    delete details.dataset.loaded;
+                    return;
+                }
+
+                const list = details.querySelector(':scope > ul');
+                for (const entry of await res.json()) {
+                    if (entry.kind === 'Resource') {
+                        continue;
+                    }
+
+                    const path = details.dataset.path + '/' + entry.name;
+                    const item = document.createElement('li');
+
+                    if (entry.kind === 'Directory') {
+                        const folder = document.createElement('details');
+                        const summary = document.createElement('summary');
+                        folder.dataset.path = path;
+                        summary.textContent = entry.name;
+                        folder.append(summary, document.createElement('ul'));
+                        item.append(folder);
+                    } else {
+                        const link = document.createElement('a');
+                        link.href = '/' + tree.dataset.user + '/notes/'
+                            + path.split('/').map(encodeURIComponent).join('/');
+                        link.textContent = entry.name;
+                        item.append(link);
+                    }
+
+                    list.append(item);
+                }
+            }, true);
+        "
+        </Script>
+        <ul id="file_tree" data-user=tree.username.clone()>
+            {file_tree_level(&tree, "")}
+        </ul>
+    }
+}
+
+/// Lists the headings of a table of contents, nesting the headings below
+/// each one in a collapsible list of their own.
+#[component]
+fn TocList(headings: Vec<TocHeading>) -> impl IntoView {
+    headings
+        .into_iter()
+        .map(|heading| {
+            let href = format!("#{}", heading.id);
+            let branch = !heading.children.is_empty();
+
+            // Sections with subheadings can be collapsed
+            let toggle = branch.then(|| view! {
+                <button class="toc_toggle" type="button" aria-expanded="true"
+                    aria-label="Toggle section"/>
+            });
+            let children = branch.then(|| view! {
+                <ul><TocList headings=heading.children/></ul>
+            });
+
+            view! {
+                <li class:toc_branch=branch data-heading=heading.id>
+                    {toggle}
+                    <a href=href>{heading.text}</a>
+                    {children}
+                </li>
+            }
+        })
+        .collect_view()
+}
+
+/// Renders the entries of a loaded folder, recursing into loaded subfolders.
+fn file_tree_level(tree: &FileTree, dir: &str) -> View {
+    let Some(entries) = tree.levels.get(dir) else {
+        return ().into_view();
+    };
+
+    entries
+        .iter()
+        .filter(|entry| entry.kind != EntryKind::Resource)
+        .map(|entry| {
+            let path = match dir.is_empty() {
+                true => entry.name.clone(),
+                false => format!("{dir}/{}", entry.name),
+            };
+
+            if entry.kind == EntryKind::Directory {
+                let loaded = tree.levels.contains_key(&path);
+                let on_path = tree.current == path
+                    || tree.current.starts_with(&format!("{path}/"));
+
+                view! {
+                    <li>
+                        <details
+                            data-path=path.clone()
+                            data-loaded=loaded.then_some("true")
+                            open=loaded
+                        >
+                            <summary class:current=on_path>{entry.name.clone()}</summary>
+                            <ul>{file_tree_level(tree, &path)}</ul>
+                        </details>
+                    </li>
+                }.into_view()
+            } else {
+                let href = format!("/{}/notes/{}", tree.username, encode_path(&path));
+                let current = tree.current == path;
+
+                view! {
+                    <li><a href=href class:current=current>{entry.name.clone()}</a></li>
+                }.into_view()
+            }
+        })
+        .collect_view()
+}
+
+/// Lists every folder leading up to the current note so that its branch of
+/// the file tree starts out expanded.
+async fn load_file_tree(username: String, path: String) -> FileTree {
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.last().is_some_and(|segment| segment.ends_with(".md")) {
+        segments.pop();
+    }
+
+    let mut levels = HashMap::new();
+    for depth in 0..=segments.len() {
+        let dir = segments[..depth].join("/");
+        if let Ok(entries) = list_directory(dir.clone()).await {
+            levels.insert(dir, entries);
+        }
+    }
+
+    FileTree {
+        username,
+        current: path.trim_matches('/').to_string(),
+        levels,
     }
 }
 
 #[component]
 pub fn Navbar(
     #[prop(default = None)]
-    toc: Option<Vec<TocHeading>>
+    toc: Option<Vec<TocHeading>>,
+    #[prop(default = None)]
+    file_tree: Option<FileTree>,
+    /// Path of the note being viewed, enables managing notes
+    #[prop(default = None)]
+    note_path: Option<String>
 ) -> impl IntoView {
     let ln_settings: LazyNotesSettings = expect_context();
     let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
         expect_context();
 
-    let toc_visible = toc.as_ref().is_some_and(|v| !v.is_empty());
+    let toc_visible = toc.as_ref().is_some_and(|v| !v.is_empty()) || file_tree.is_some();
     let send_logout = create_server_action::<auth::Logout>();
 
     view! {
@@ -257,7 +473,32 @@ pub fn Navbar(
             </section>
             <section class="right_nav">
                 {move || if auth.is_authenticated() {
+                    let search_url = auth.current_user.as_ref()
+                        .map(|user| format!("/{}/search", &user.username));
+                    let tags_url = auth.current_user.as_ref()
+                        .map(|user| format!("/{}/tags", &user.username))
+                        .unwrap_or_default();
+                    let uploads_url = auth.current_user.as_ref()
+                        .map(|user| format!("/{}/uploads", &user.username))
+                        .unwrap_or_default();
+                    let trash_url = auth.current_user.as_ref()
+                        .map(|user| format!("/{}/trash", &user.username))
+                        .unwrap_or_default();
+
+                    let file_menu = auth.current_user.as_ref()
+                        .zip(note_path.clone())
+                        .map(|(user, path)| view! {
+                            <FileMenu username=user.username.clone() path=path/>
+                        });
+
                     view! {
+                        {file_menu}
+                        <form class="search_box" method="get" action=search_url>
+                            <input type="search" name="q" placeholder="Search notes"/>
+                        </form>
+                        <A class="tags_btn" href=tags_url>"Tags"</A>
+                        <A class="uploads_btn" href=uploads_url>"Uploads"</A>
+                        <A class="trash_btn" href=trash_url>"Trash"</A>
                         <ActionForm action=send_logout>
                             <input class="logout_btn" type="submit" value="Log out"/>
                         </ActionForm>
@@ -273,13 +514,104 @@ pub fn Navbar(
             </section>
         </nav>
         <Show when=move || toc_visible>
-            // TODO: Proper error handling
-            // Should only fail if bad HTML which should not happen due to sanitization
-            <TocSidebar toc=toc.clone().expect("Invalid HTML while parsing headings")/>
+            // TOC is missing if the note failed to load or had invalid HTML
+            <TocSidebar toc=toc.clone().unwrap_or_default() file_tree=file_tree.clone()/>
         </Show>
     }
 }
 
+#[component]
+pub fn FileMenu(username: String, path: String) -> impl IntoView {
+    let path = path.trim_matches('/').to_string();
+    let folder = match path.ends_with(".md") {
+        true => path.rsplit_once('/').map_or("", |(folder, _)| folder).to_string(),
+        false => path.clone(),
+    };
+    let prefix = match folder.is_empty() {
+        true => String::new(),
+        false => format!("{folder}/"),
+    };
+
+    view! {
+        // Create, rename and delete notes through the matching server functions
+        <Script>
+        "
+            document.addEventListener('submit', async ev => {
+                const form = ev.target;
+                const menu = form.closest('.file_menu');
+                if (!menu) {
+                    return;
+                }
+
+                ev.preventDefault();
+                if (form.dataset.confirm && !confirm(form.dataset.confirm)) {
+                    return;
+                }
+
+                const params = new URLSearchParams(new FormData(form));
+                for (const box of form.querySelectorAll('input[type=checkbox]')) {
+                    params.set(box.name, box.checked);
+                }
+
+                const res = await fetch('/api/' + form.dataset.action, {
+                    method: 'POST',
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/x-www-form-urlencoded',
+                    },
+                    body: params,
+                });
+
+                if (!res.ok) {
+                    // Server function errors are prefixed with their kind
+                    alert((await res.text()).replace(/^\\w+\\|/, ''));
+                    return;
+                }
+
+                // Renames also list the notes whose links couldn't be updated
+                const result = await res.json();
+                const path = typeof result === 'string' ? result : result.path;
+                if (result.not_updated && result.not_updated.length) {
+                    alert('Links could not be updated in:\\n' + result.not_updated.join('\\n'));
+                }
+                location.href = '/' + menu.dataset.user + '/notes/'
+                    + path.split('/').map(encodeURIComponent).join('/')
+                    + (form.dataset.edit ? '?edit' : '');
+            });
+        "
+        </Script>
+        <details class="file_menu" data-user=username>
+            <summary>"File"</summary>
+            <div class="file_menu_items">
+                <form data-action="create_note" data-edit="true">
+                    <input type="text" name="path" value=prefix.clone()
+                           placeholder="folder/note.md" required/>
+                    <input type="submit" value="New note"/>
+                </form>
+                <form data-action="create_directory">
+                    <input type="text" name="path" value=prefix placeholder="folder" required/>
+                    <input type="submit" value="New folder"/>
+                </form>
+                {(!path.is_empty()).then(|| view! {
+                    <form data-action="rename_entry">
+                        <input type="hidden" name="from" value=path.clone()/>
+                        <input type="text" name="to" value=path.clone() required/>
+                        <label>
+                            <input type="checkbox" name="update_links" checked/>
+                            "Update links"
+                        </label>
+                        <input type="submit" value="Rename or move"/>
+                    </form>
+                    <form data-action="delete_entry" data-confirm=format!("Move {path} to the trash?")>
+                        <input type="hidden" name="path" value=path.clone()/>
+                        <input type="submit" value="Delete"/>
+                    </form>
+                })}
+            </div>
+        </details>
+    }
+}
+
 #[component]
 pub fn Signup() -> impl IntoView {
     let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
@@ -287,10 +619,9 @@ pub fn Signup() -> impl IntoView {
 
     // If authenticated, redirect to user notes page
     if auth.is_authenticated() {
-        auth.current_user.and_then(|user| {
+        if let Some(user) = auth.current_user {
             leptos_axum::redirect(&format!("/{}/notes/index.md", &user.username));
-            Some(())
-        });
+        }
     }
 
     let send_signup = create_server_action::<auth::Signup>();
@@ -316,9 +647,10 @@ pub fn Signup() -> impl IntoView {
                             .into_iter()
                             .map(|(_, e)| view! {
                                 <p class="error">
-                                {format!("{}", e.to_string()
+                                {e.to_string()
                                     .strip_prefix("error running server function: ")
-                                    .unwrap_or_else(|| "Incorrect field(s)"))}
+                                    .unwrap_or("Incorrect field(s)")
+                                    .to_owned()}
                                 </p>
                             }).collect_view()
                     }>
@@ -339,10 +671,9 @@ pub fn Login() -> impl IntoView {
 
     // If authenticated, redirect to user notes page
     if auth.is_authenticated() {
-        auth.current_user.and_then(|user| {
+        if let Some(user) = auth.current_user {
             leptos_axum::redirect(&format!("/{}/notes/index.md", &user.username));
-            Some(())
-        });
+        }
     }
 
     let send_login = create_server_action::<auth::Login>();
@@ -370,9 +701,10 @@ pub fn Login() -> impl IntoView {
                             .into_iter()
                             .map(|(_, e)| view! {
                                 <p class="error">
-                                {format!("{}", e.to_string()
+                                {e.to_string()
                                     .strip_prefix("error running server function: ")
-                                    .unwrap_or_else(|| "Incorrect username or password"))}
+                                    .unwrap_or("Incorrect username or password")
+                                    .to_owned()}
                                 </p>
                             }).collect_view()
                     }>
@@ -421,20 +753,159 @@ pub fn Note() -> impl IntoView {
         return view! { <Unauthorized/> };
     }
 
+    // Notes are opened in the editor with `?edit`
+    let query = use_query_map();
+    if query.with(|query| query.get("edit").is_some()) {
+        let path = params.with(|params| {
+            params.as_ref().map(|params| params.path.clone()).unwrap_or_default()
+        });
+        return view! { <NoteEditor username=user.username.clone() path=path/> };
+    }
+
+    // Past versions are browsed with `?history`
+    if query.with(|query| query.get("history").is_some()) {
+        let path = params.with(|params| {
+            params.as_ref().map(|params| params.path.clone()).unwrap_or_default()
+        });
+        return view! { <NoteHistory path=path/> };
+    }
+
     let notes_as_html = create_blocking_resource(move || (), move |_| {
         let path = params.get().map(|params| params.path).unwrap_or("".into());
         async move { get_note_as_html(path).await }}
     );
 
+    let username = user.username.clone();
+    let file_tree = create_blocking_resource(move || (), move |_| {
+        let path = params.get().map(|params| params.path).unwrap_or("".into());
+        load_file_tree(username.clone(), path)
+    });
+
+    let backlinks = create_blocking_resource(move || (), move |_| {
+        let path = params.get().map(|params| params.path).unwrap_or("".into());
+        async move { get_backlinks(path).await }
+    });
+
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+    let tags_username = store_value(user.username.clone());
+    let live_username = store_value(user.username.clone());
+    let live_path = store_value(params.with(|params| {
+        params.as_ref().map(|params| params.path.trim_matches('/').to_string()).unwrap_or_default()
+    }));
+
     view! {
+        // Check off tasks and reload the note when its file changes on disk
+        <Script>
+        "
+            window.addEventListener('DOMContentLoaded', () => {
+                const wrapper = document.getElementById('notes_wrapper');
+                const notes = document.getElementById('notes');
+                if (!wrapper || !notes || !notes.dataset.user) {
+                    return;
+                }
+
+                const path = notes.dataset.path;
+                const index = path ? path + '/index.md' : 'index.md';
+                let timer = null;
+
+                // Tasks rendered from the note's file can be checked off
+                const enableTasks = () => {
+                    for (const box of wrapper.querySelectorAll('input.task[data-offset]')) {
+                        box.disabled = false;
+                    }
+                };
+
+                // Re-render the whole note view, including its TOC, tags and backlinks,
+                // without moving the page
+                const reload = async () => {
+                    const res = await fetch(location.pathname, {
+                        headers: { 'Accept': 'text/html' },
+                    });
+                    if (!res.ok) {
+                        return;
+                    }
+
+                    const page = new DOMParser().parseFromString(await res.text(), 'text/html');
+                    const fresh = page.getElementById('notes_wrapper');
+                    if (!fresh) {
+                        return;
+                    }
+
+                    const { scrollX, scrollY } = window;
+                    wrapper.innerHTML = fresh.innerHTML;
+                    const toc = document.getElementById('toc');
+                    const freshToc = page.getElementById('toc');
+                    if (toc && freshToc) {
+                        toc.innerHTML = freshToc.innerHTML;
+                    }
+                    document.title = page.title;
+                    enableTasks();
+                    document.dispatchEvent(new Event('note_reloaded'));
+                    window.scrollTo(scrollX, scrollY);
+                };
+
+                wrapper.addEventListener('change', async ev => {
+                    const box = ev.target;
+                    const notes = document.getElementById('notes');
+                    if (!notes || !box.matches('input.task[data-offset]')) {
+                        return;
+                    }
+
+                    box.disabled = true;
+                    const res = await fetch('/api/toggle_task', {
+                        method: 'POST',
+                        headers: {
+                            'Accept': 'application/json',
+                            'Content-Type': 'application/x-www-form-urlencoded',
+                        },
+                        body: new URLSearchParams({
+                            path,
+                            offset: box.dataset.offset,
+                            checked: box.checked,
+                            version: notes.dataset.version,
+                        }),
+                    });
+
+                    if (res.ok) {
+                        notes.dataset.version = await res.json();
+                    } else {
+                        box.checked = !box.checked;
+                        // Server function errors are prefixed with their kind
+                        alert((await res.text()).replace(/^\\w+\\|/, ''));
+                    }
+                    box.disabled = false;
+                });
+                enableTasks();
+
+                const events = new EventSource('/' + notes.dataset.user + '/events');
+                events.addEventListener('change', ev => {
+                    // Folders show their index.md or a listing of their direct entries
+                    const changed = ev.data;
+                    const parent = changed.includes('/')
+                        ? changed.slice(0, changed.lastIndexOf('/'))
+                        : '';
+                    if (changed === '*' || changed === path || changed === index
+                        || parent === path) {
+                        // Editors often write a file several times in a row
+                        clearTimeout(timer);
+                        timer = setTimeout(reload, 200);
+                    }
+                });
+            });
+        "
+        </Script>
         <Suspense fallback=move || view! {
             <article id="notes_wrapper">
                 <p>"Getting your notes..."</p>
             </article>
         }>
-            <Navbar toc=notes_as_html.get()
-                .and_then(|notes| notes.ok())
-                .and_then(|notes| generate_toc(&notes).ok())/>
+            <Navbar
+                toc=notes_as_html.get()
+                    .and_then(|notes| notes.ok())
+                    .map(|notes| notes.toc)
+                file_tree=file_tree.get()
+                note_path=params.get().map(|params| params.path).ok()/>
             <article id="notes_wrapper">
                 {move || notes_as_html.get()
                     .transpose()
@@ -444,168 +915,1012 @@ pub fn Note() -> impl IntoView {
                                 <p>
                                 {move || e.to_string()
                                     .strip_prefix("error running server function: ")
-                                    .unwrap_or_else(|| "Failed to get note")
+                                    .unwrap_or("Failed to get note")
                                     .to_owned()}
                                 </p>
                             </article>
-                        }
+                        }.into_view()
                     })
-                    .map(|notes| view! { <article id="notes" inner_html=notes/> })
+                    .map(|notes| notes
+                        .map(|notes| view! {
+                            {notes.metadata.title.map(|title| view! { <Title text=title/> })}
+                            <nav class="note_actions">
+                                <a href="?edit">"Edit"</a>
+                                <a href="?history">"History"</a>
+                            </nav>
+                            <NoteTags username=tags_username.get_value() tags=notes.metadata.tags/>
+                            <article id="notes" data-user=live_username.get_value()
+                                     data-path=live_path.get_value() data-version=notes.version
+                                     inner_html=notes.html/>
+                        })
+                        .into_view())
                     .unwrap_or_else(|e| e)
                 }
+                {move || backlinks.get()
+                    .and_then(|backlinks| backlinks.ok())
+                    .filter(|backlinks| !backlinks.is_empty())
+                    .map(|backlinks| view! {
+                        <Backlinks username=username.get_value() backlinks=backlinks/>
+                    })
+                }
             </article>
         </Suspense>
     }.into_view()
 }
 
 #[component]
-pub fn Unauthorized() -> impl IntoView {
+pub fn NoteEditor(username: String, path: String) -> impl IntoView {
+    let source_path = path.clone();
+    let source = create_blocking_resource(move || (), move |_| get_note_source(source_path.clone()));
+    let note_url = format!("/{username}/notes/{}", encode_path(&path));
+    let upload_url = format!("/{username}/upload");
+    let upload_folder = uploads::note_folder(&path);
+
+    // Stored so the Suspense children can be rendered more than once
+    let path = store_value(path);
+    let note_url = store_value(note_url);
+    let upload_url = store_value(upload_url);
+    let upload_folder = store_value(upload_folder);
+
     view! {
         <Navbar/>
-        <article class="no_permission">
-            <p>"You do not have permission to view this page."</p>
+        // Save with conflict detection, keyboard shortcuts, live preview and pasted uploads
+        <Script>
+        "
+            window.addEventListener('DOMContentLoaded', () => {
+                const editor = document.getElementById('editor');
+                if (!editor) {
+                    return;
+                }
+
+                const source = document.getElementById('editor_source');
+                const preview = editor.querySelector('.editor_preview');
+                const status = document.getElementById('editor_status');
+                let saved = source.value;
+                let timer = null;
+
+                const call = (endpoint, params) => fetch('/api/' + endpoint, {
+                    method: 'POST',
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/x-www-form-urlencoded',
+                    },
+                    body: new URLSearchParams(params),
+                });
+
+                const setStatus = (text, isError) => {
+                    status.textContent = text;
+                    status.classList.toggle('error', !!isError);
+                };
+
+                // Uploads files into the note's resources folder and inserts
+                // their markdown at the cursor
+                const upload = async files => {
+                    if (files.length === 0) {
+                        return;
+                    }
+
+                    const form = new FormData();
+                    form.append('folder', editor.dataset.uploadFolder);
+                    for (const file of files) {
+                        form.append('file', file);
+                    }
+
+                    setStatus('Uploading...');
+                    const res = await fetch(editor.dataset.uploadUrl, {
+                        method: 'POST',
+                        headers: { 'Accept': 'application/json' },
+                        body: form,
+                    });
+
+                    if (!res.ok) {
+                        setStatus(await res.text(), true);
+                        return;
+                    }
+
+                    const snippets = (await res.json()).map(file => file.markdown).join('\n');
+                    source.focus();
+                    source.setRangeText(snippets, source.selectionStart, source.selectionEnd, 'end');
+                    source.dispatchEvent(new Event('input'));
+                };
+
+                source.addEventListener('paste', ev => {
+                    const files = [...ev.clipboardData.files];
+                    if (files.length === 0) {
+                        return;
+                    }
+
+                    ev.preventDefault();
+                    // Clipboard images are all called image.png, so name them by time instead
+                    const stamp = new Date().toISOString().replace(/\\D/g, '').slice(0, 14);
+                    upload(files.map(file => file.name === 'image.png'
+                        ? new File([file], `pasted-${stamp}.png`, { type: file.type })
+                        : file));
+                });
+
+                source.addEventListener('dragover', ev => {
+                    if (ev.dataTransfer.types.includes('Files')) {
+                        ev.preventDefault();
+                        source.classList.add('dragging');
+                    }
+                });
+
+                source.addEventListener('dragleave', () => source.classList.remove('dragging'));
+
+                source.addEventListener('drop', ev => {
+                    source.classList.remove('dragging');
+                    if (ev.dataTransfer.files.length === 0) {
+                        return;
+                    }
+
+                    ev.preventDefault();
+                    upload([...ev.dataTransfer.files]);
+                });
+
+                const updatePreview = async () => {
+                    const res = await call('preview_note', { content: source.value });
+                    if (res.ok) {
+                        preview.innerHTML = (await res.json()).html;
+                    }
+                };
+
+                source.addEventListener('input', () => {
+                    clearTimeout(timer);
+                    timer = setTimeout(updatePreview, 300);
+                    setStatus(source.value === saved ? '' : 'Unsaved changes');
+                });
+
+                editor.addEventListener('submit', async ev => {
+                    ev.preventDefault();
+                    setStatus('Saving...');
+
+                    const content = source.value;
+                    const res = await call('save_note', {
+                        path: editor.dataset.path,
+                        content,
+                        version: editor.dataset.version,
+                    });
+
+                    if (!res.ok) {
+                        // Server function errors are prefixed with their kind
+                        setStatus((await res.text()).replace(/^\\w+\\|/, ''), true);
+                        return;
+                    }
+
+                    editor.dataset.version = await res.json();
+                    saved = content;
+                    setStatus(source.value === saved ? 'Saved' : 'Unsaved changes');
+                });
+
+                document.addEventListener('keydown', ev => {
+                    if ((ev.ctrlKey || ev.metaKey) && ev.key === 's') {
+                        ev.preventDefault();
+                        editor.requestSubmit();
+                    }
+                });
+
+                window.addEventListener('beforeunload', ev => {
+                    if (source.value !== saved) {
+                        ev.preventDefault();
+                    }
+                });
+
+                updatePreview();
+            });
+        "
+        </Script>
+        <Suspense fallback=move || view! {
+            <article id="notes_wrapper">
+                <p>"Opening your note..."</p>
+            </article>
+        }>
+            {move || source.get().map(|source| match source {
+                Ok(source) => view! {
+                    <form id="editor" data-path=path.get_value() data-version=source.version
+                          data-upload-url=upload_url.get_value()
+                          data-upload-folder=upload_folder.get_value()>
+                        <div class="editor_toolbar">
+                            <a href=note_url.get_value()>"Back to note"</a>
+                            <span id="editor_status"></span>
+                            <input type="submit" value="Save"/>
+                        </div>
+                        <div class="editor_panes">
+                            <textarea id="editor_source" name="content" spellcheck="false">{source.content}</textarea>
+                            <article id="notes" class="editor_preview"></article>
+                        </div>
+                    </form>
+                }.into_view(),
+                Err(e) => view! {
+                    <article id="notes_wrapper">
+                        <article id="notes_error">
+                            <p>
+                            {e.to_string()
+                                .strip_prefix("error running server function: ")
+                                .unwrap_or("Failed to open note")
+                                .to_owned()}
+                            </p>
+                        </article>
+                    </article>
+                }.into_view(),
+            })}
+        </Suspense>
+    }
+}
+
+#[component]
+pub fn NoteHistory(path: String) -> impl IntoView {
+    let query = use_query_map();
+    let param = move |name: &str| query.with(|query| query.get(name).cloned()).unwrap_or_default();
+    let (revision, from, to) = (param("rev"), param("from"), param("to"));
+
+    let history_path = path.clone();
+    let history = create_blocking_resource(move || (), move |_| get_note_history(history_path.clone()));
+    // Restoring a revision only replaces the version of the note shown here
+    let source_path = path.clone();
+    let source = create_blocking_resource(move || (), move |_| get_note_source(source_path.clone()));
+
+    let revision_path = path.clone();
+    let shown_revision = revision.clone();
+    let rendered = create_blocking_resource(move || (), move |_| {
+        let (path, revision) = (revision_path.clone(), shown_revision.clone());
+        async move {
+            match revision.is_empty() {
+                true => None,
+                false => Some(get_note_revision(path, revision).await),
+            }
+        }
+    });
+
+    let diff_path = path.clone();
+    let (diff_from, diff_to) = (from.clone(), to.clone());
+    let diff = create_blocking_resource(move || (), move |_| {
+        let (path, from, to) = (diff_path.clone(), diff_from.clone(), diff_to.clone());
+        async move {
+            match from.is_empty() {
+                true => None,
+                false => Some(get_note_diff(path, from, to).await),
+            }
+        }
+    });
+
+    let error_message = |e: ServerFnError, fallback: &'static str| view! {
+        <p class="error">
+        {e.to_string()
+            .strip_prefix("error running server function: ")
+            .unwrap_or(fallback)
+            .to_owned()}
+        </p>
+    };
+    let short = |id: &str| id.chars().take(7).collect::<String>();
+    let diff_title = match to.is_empty() {
+        true => format!("Changes since {}", short(&from)),
+        false => format!("Changes from {} to {}", short(&from), short(&to)),
+    };
+    let revision_title = format!("Revision {}", short(&revision));
+    // Stored so the Suspense children can be rendered more than once
+    let diff_title = store_value(diff_title);
+    let revision_title = store_value(revision_title);
+
+    view! {
+        // Restore revisions, asking first as the current note is replaced
+        <Script>
+        "
+            document.addEventListener('submit', async ev => {
+                const form = ev.target;
+                const page = form.closest('.history_page');
+                if (!page || form.dataset.action !== 'restore_note_revision') {
+                    return;
+                }
+
+                ev.preventDefault();
+                if (!confirm('Replace the note with this revision?')) {
+                    return;
+                }
+
+                const params = new URLSearchParams(new FormData(form));
+                params.set('path', page.dataset.path);
+                params.set('version', document.getElementById('note_version')?.value ?? '');
+                const res = await fetch('/api/restore_note_revision', {
+                    method: 'POST',
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/x-www-form-urlencoded',
+                    },
+                    body: params,
+                });
+
+                if (!res.ok) {
+                    // Server function errors are prefixed with their kind
+                    alert((await res.text()).replace(/^\\w+\\|/, ''));
+                    return;
+                }
+
+                location.href = '?';
+            });
+        "
+        </Script>
+        <Navbar/>
+        <article id="notes_wrapper" class="history_page" data-path=path.clone()>
+            <nav class="note_actions">
+                <a href="?">"Back to note"</a>
+            </nav>
+            <h1>"History of "{path}</h1>
+            <Suspense fallback=move || view! { <p>"Digging up the past..."</p> }>
+                {move || source.get().and_then(Result::ok).map(|source| view! {
+                    <input type="hidden" id="note_version" value=source.version/>
+                })}
+                {move || rendered.get().flatten().map(|note| match note {
+                    Ok(note) => view! {
+                        <section class="revision">
+                            <h2>{revision_title.get_value()}</h2>
+                            <article id="notes" inner_html=note.html/>
+                        </section>
+                    }.into_view(),
+                    Err(e) => error_message(e, "Failed to get revision").into_view(),
+                })}
+                {move || diff.get().flatten().map(|diff| match diff {
+                    Ok(diff) => view! {
+                        <section class="revision">
+                            <h2>{diff_title.get_value()}</h2>
+                            <NoteDiff diff=diff/>
+                        </section>
+                    }.into_view(),
+                    Err(e) => error_message(e, "Failed to compare revisions").into_view(),
+                })}
+                {move || history.get().map(|history| match history {
+                    Ok(revisions) if revisions.is_empty() => view! {
+                        <p>"No earlier versions of this note were found."</p>
+                    }.into_view(),
+                    Ok(revisions) => {
+                        let previous = revisions.iter()
+                            .skip(1)
+                            .map(|revision| Some(revision.id.clone()))
+                            .chain([None]);
+                        let rows = revisions.iter()
+                            .zip(previous)
+                            .map(|(revision, previous)| view! {
+                                <RevisionRow revision=revision.clone() previous=previous/>
+                            })
+                            .collect_view();
+
+                        view! {
+                            <table class="dir_listing">
+                                <thead>
+                                    <tr>
+                                        <th>"Revision"</th>
+                                        <th>"Author"</th>
+                                        <th>"Date"</th>
+                                        <th></th>
+                                    </tr>
+                                </thead>
+                                <tbody>{rows}</tbody>
+                            </table>
+                        }.into_view()
+                    }
+                    Err(e) => error_message(e, "Failed to get history").into_view(),
+                })}
+            </Suspense>
         </article>
     }
 }
 
-/// Generate a table of contents from HTML by parsing heading elements.
-fn generate_toc(html: &str) -> Result<Vec<TocHeading>, String> {
-    if !is_html(html) {
-        return Err("Invalid HTML".to_string());
+#[component]
+pub fn RevisionRow(revision: Revision, previous: Option<String>) -> impl IntoView {
+    let id = revision.id;
+
+    view! {
+        <tr>
+            <td>
+                <a href=format!("?history&rev={id}")>{revision.summary}</a>
+                " "<code class="revision_id">{id.chars().take(7).collect::<String>()}</code>
+            </td>
+            <td>{revision.author}</td>
+            <td>{format_timestamp(revision.time)}</td>
+            <td class="revision_actions">
+                {previous.map(|previous| view! {
+                    <a href=format!("?history&from={previous}&to={id}")>"Changes"</a>
+                })}
+                <a href=format!("?history&from={id}")>"Compare with now"</a>
+                <form data-action="restore_note_revision">
+                    <input type="hidden" name="revision" value=id.clone()/>
+                    <input type="submit" value="Restore"/>
+                </form>
+            </td>
+        </tr>
     }
+}
 
-    let sink = TocSink { headings: Vec::new() };
+#[component]
+pub fn NoteDiff(diff: Vec<DiffLine>) -> impl IntoView {
+    if diff.iter().all(|line| matches!(line, DiffLine::Skipped(_))) {
+        return view! { <p>"No changes."</p> }.into_view();
+    }
 
-    // Prepare input
-    let mut input = BufferQueue::new();
-    input.push_back(StrTendril::from_slice(html));
+    view! {
+        <pre class="diff">
+            {diff.into_iter()
+                .map(|line| match line {
+                    DiffLine::Unchanged(text) => view! { <span class="diff_unchanged">"  "{text}</span> },
+                    DiffLine::Added(text) => view! { <span class="diff_added">"+ "{text}</span> },
+                    DiffLine::Removed(text) => view! { <span class="diff_removed">"- "{text}</span> },
+                    DiffLine::Skipped(count) => view! {
+                        <span class="diff_skipped">{format!("⋯ {count} unchanged lines")}</span>
+                    },
+                })
+                .collect_view()}
+        </pre>
+    }.into_view()
+}
 
-    // Parse
-    let mut tokenizer = Tokenizer::new(sink, Default::default());
-    let _ = tokenizer.feed(&mut input);
-    tokenizer.end();
+#[component]
+pub fn NoteTags(username: String, tags: Vec<String>) -> impl IntoView {
+    (!tags.is_empty()).then(|| view! {
+        <ul class="tags">
+            {tags.into_iter()
+                .map(|tag| {
+                    let href = format!("/{username}/tags/{}", encode_path(&tag.to_lowercase()));
+                    view! { <li><a class="tag" href=href>"#"{tag}</a></li> }
+                })
+                .collect_view()}
+        </ul>
+    })
+}
 
-    Ok(tokenizer.sink.headings)
+#[component]
+pub fn Backlinks(username: String, backlinks: Vec<Backlink>) -> impl IntoView {
+    view! {
+        <section id="backlinks">
+            <h2>"Linked from"</h2>
+            <ul>
+                {backlinks.into_iter()
+                    .map(|backlink| {
+                        let href = format!("/{username}/notes/{}", encode_path(&backlink.path));
+                        view! {
+                            <li>
+                                <a href=href>{backlink.title}</a>
+                                <p>{backlink.context}</p>
+                            </li>
+                        }
+                    })
+                    .collect_view()}
+            </ul>
+        </section>
+    }
+}
+
+#[component]
+pub fn Search() -> impl IntoView {
+    let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
+        expect_context();
+    let response: ResponseOptions = expect_context();
+
+    if !auth.is_authenticated() {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let user = auth.current_user.clone().expect("User was not authenticated");
+    let params = use_params::<UserParams>();
+
+    // Users may only search their own notes
+    if !params.with(|params|
+        params.as_ref()
+            .map(|params| user.username == params.user)
+            .is_ok_and(|authenticated| authenticated))
+    {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let query = use_query_map();
+    let search_query = move || query.with(|query| query.get("q").cloned().unwrap_or_default());
+
+    let results = create_blocking_resource(move || (), move |_| {
+        let query = search_query();
+        async move { search_notes(query).await }
+    });
+
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+
+    view! {
+        <Navbar/>
+        <article id="notes_wrapper" class="search">
+            <form class="search_form" method="get">
+                <input type="search" name="q" value=search_query() placeholder="Search notes"/>
+                <input type="submit" value="Search"/>
+            </form>
+            <Suspense fallback=move || view! { <p>"Searching your notes..."</p> }>
+                {move || results.get().map(|results| match results {
+                    Ok(_) if search_query().trim().is_empty() => ().into_view(),
+                    Ok(hits) if hits.is_empty() => view! {
+                        <p>"No notes matched your search."</p>
+                    }.into_view(),
+                    Ok(hits) => view! {
+                        <ol class="search_results">
+                            {hits.into_iter()
+                                .map(|hit| view! { <SearchResult username=username.get_value() hit=hit/> })
+                                .collect_view()}
+                        </ol>
+                    }.into_view(),
+                    Err(e) => view! {
+                        <p class="error">
+                        {e.to_string()
+                            .strip_prefix("error running server function: ")
+                            .unwrap_or("Failed to search notes")
+                            .to_owned()}
+                        </p>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </article>
+    }.into_view()
+}
+
+#[component]
+pub fn SearchResult(username: String, hit: SearchHit) -> impl IntoView {
+    let href = match &hit.anchor {
+        Some(anchor) => format!("/{username}/notes/{}#{anchor}", encode_path(&hit.path)),
+        None => format!("/{username}/notes/{}", encode_path(&hit.path)),
+    };
+
+    // The first heading is usually the title so don't repeat it
+    let heading = hit.heading.filter(|heading| *heading != hit.title);
+
+    view! {
+        <li>
+            <a href=href>
+                <span class="search_title">{hit.title}</span>
+                {heading.map(|heading| view! { <span class="search_heading">{heading}</span> })}
+            </a>
+            <span class="search_path">{hit.path}</span>
+            <p class="search_snippet">
+                {hit.snippet.into_iter()
+                    .map(|part| match part.highlight {
+                        true => view! { <mark>{part.text}</mark> }.into_view(),
+                        false => part.text.into_view(),
+                    })
+                    .collect_view()}
+            </p>
+        </li>
+    }
+}
+
+#[component]
+pub fn Tags() -> impl IntoView {
+    let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
+        expect_context();
+    let response: ResponseOptions = expect_context();
+
+    if !auth.is_authenticated() {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let user = auth.current_user.clone().expect("User was not authenticated");
+    let params = use_params::<UserParams>();
+
+    // Users may only see their own tags
+    if !params.with(|params|
+        params.as_ref()
+            .map(|params| user.username == params.user)
+            .is_ok_and(|authenticated| authenticated))
+    {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let tags = create_blocking_resource(move || (), move |_| list_tags());
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+
+    view! {
+        <Navbar/>
+        <article id="notes_wrapper" class="tags_page">
+            <h1>"Tags"</h1>
+            <Suspense fallback=move || view! { <p>"Collecting your tags..."</p> }>
+                {move || tags.get().map(|tags| match tags {
+                    Ok(tags) if tags.is_empty() => view! {
+                        <p>"None of your notes have tags yet."</p>
+                    }.into_view(),
+                    Ok(tags) => view! {
+                        <ul class="tag_list">
+                            {tags.into_iter()
+                                .map(|tag| view! { <TagListItem username=username.get_value() tag=tag/> })
+                                .collect_view()}
+                        </ul>
+                    }.into_view(),
+                    Err(e) => view! {
+                        <p class="error">
+                        {e.to_string()
+                            .strip_prefix("error running server function: ")
+                            .unwrap_or("Failed to get tags")
+                            .to_owned()}
+                        </p>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </article>
+    }.into_view()
+}
+
+#[component]
+pub fn TagListItem(username: String, tag: TagCount) -> impl IntoView {
+    let href = format!("/{username}/tags/{}", encode_path(&tag.tag));
+    // Nested tags are indented below their parents
+    let depth = tag.tag.matches('/').count();
+
+    view! {
+        <li style=format!("--depth: {depth}")>
+            <a class="tag" href=href>"#"{tag.tag}</a>
+            <span class="tag_count">{tag.count}</span>
+        </li>
+    }
+}
+
+#[component]
+pub fn Tag() -> impl IntoView {
+    let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
+        expect_context();
+    let response: ResponseOptions = expect_context();
+
+    if !auth.is_authenticated() {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let user = auth.current_user.clone().expect("User was not authenticated");
+    let params = use_params::<TagParams>();
+
+    // Users may only see their own tags
+    if !params.with(|params|
+        params.as_ref()
+            .map(|params| user.username == params.user)
+            .is_ok_and(|authenticated| authenticated))
+    {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let tag = move || params.get().map(|params| params.tag).unwrap_or_default();
+    let notes = create_blocking_resource(move || (), move |_| get_tagged_notes(tag()));
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+
+    view! {
+        <Navbar/>
+        <article id="notes_wrapper" class="tags_page">
+            <h1>"#"{tag}</h1>
+            <Suspense fallback=move || view! { <p>"Finding tagged notes..."</p> }>
+                {move || notes.get().map(|notes| match notes {
+                    Ok(notes) if notes.is_empty() => view! {
+                        <p>"No notes carry this tag."</p>
+                    }.into_view(),
+                    Ok(notes) => view! {
+                        <table class="dir_listing">
+                            <thead>
+                                <tr><th>"Note"</th><th>"Path"</th><th>"Modified"</th></tr>
+                            </thead>
+                            <tbody>
+                                {notes.into_iter()
+                                    .map(|note| view! { <TaggedNoteRow username=username.get_value() note=note/> })
+                                    .collect_view()}
+                            </tbody>
+                        </table>
+                    }.into_view(),
+                    Err(e) => view! {
+                        <p class="error">
+                        {e.to_string()
+                            .strip_prefix("error running server function: ")
+                            .unwrap_or("Failed to get tagged notes")
+                            .to_owned()}
+                        </p>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </article>
+    }.into_view()
 }
-}}
 
-#[cfg(feature = "ssr")]
-#[cfg(test)]
-mod tests {
-    use crate::app::{generate_toc, TocHeading};
-
-    #[test]
-    fn toc_generation() {
-        let invalid_html = "# Markdown Title";
-        let basic_html = r#"<h1 id="test"></h1>"#;
-        let html_no_id = r#"<h1></h1>"#;
-        let html_only_class = r#"<h1 class="test"></h1>"#;
-        let html_with_class_at_start = r#"<h1 class="class" id="test"></h1>"#;
-        let html_with_class_at_end = r#"<h1 id="test" class="class"></h1>"#;
-        let html_with_text = r#"<h1>test</h1>"#;
-        let html_with_text_and_attrs = r#"<h1 id="test" class="test">test</h1>"#;
-        let long_html = r#"<h1 id="test"></h1><h2 class="hello">hello</h2><h5 id="test2" class="success"></h5><h6 class="success" id="test3">test3</h6>"#;
-
-        assert_eq!(generate_toc(invalid_html), Err("Invalid HTML".to_string()));
-
-        assert_eq!(
-            generate_toc(basic_html),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: None,
-                id: Some(Box::from("test")),
-                text: None
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_no_id),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: None,
-                id: None,
-                text: None
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_only_class),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: Some(Box::from("test")),
-                id: None,
-                text: None
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_with_class_at_start),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: Some(Box::from("class")),
-                id: Some(Box::from("test")),
-                text: None
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_with_class_at_end),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: Some(Box::from("class")),
-                id: Some(Box::from("test")),
-                text: None
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_with_text),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: None,
-                id: None,
-                text: Some(Box::from("test"))
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(html_with_text_and_attrs),
-            Ok(vec![TocHeading {
-                level: 1,
-                class: Some(Box::from("test")),
-                id: Some(Box::from("test")),
-                text: Some(Box::from("test"))
-            }])
-        );
-
-        assert_eq!(
-            generate_toc(long_html),
-            Ok(vec![
-                TocHeading {
-                    level: 1,
-                    class: None,
-                    id: Some(Box::from("test")),
-                    text: None
-                },
-                TocHeading {
-                    level: 2,
-                    class: Some(Box::from("hello")),
-                    id: None,
-                    text: Some(Box::from("hello"))
-                },
-                TocHeading {
-                    level: 5,
-                    class: Some(Box::from("success")),
-                    id: Some(Box::from("test2")),
-                    text: None
-                },
-                TocHeading {
-                    level: 6,
-                    class: Some(Box::from("success")),
-                    id: Some(Box::from("test3")),
-                    text: Some(Box::from("test3"))
+#[component]
+pub fn TaggedNoteRow(username: String, note: TaggedNote) -> impl IntoView {
+    let href = format!("/{username}/notes/{}", encode_path(&note.path));
+
+    view! {
+        <tr>
+            <td><a href=href>{note.title}</a></td>
+            <td class="search_path">{note.path}</td>
+            <td>{note.modified.map(format_timestamp)}</td>
+        </tr>
+    }
+}
+
+#[component]
+pub fn Uploads() -> impl IntoView {
+    let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
+        expect_context();
+    let response: ResponseOptions = expect_context();
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    if !auth.is_authenticated() {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let user = auth.current_user.clone().expect("User was not authenticated");
+    let params = use_params::<UserParams>();
+
+    // Users may only see their own uploads
+    if !params.with(|params|
+        params.as_ref()
+            .map(|params| user.username == params.user)
+            .is_ok_and(|authenticated| authenticated))
+    {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let files = create_blocking_resource(move || (), move |_| list_resources());
+    let upload_url = format!("/{}/upload", user.username);
+    let accept = ln_settings.allowed_upload_types.join(",");
+    let limits = format!(
+        "Up to {} per file and {} files at once",
+        format_size(ln_settings.max_upload_size),
+        ln_settings.max_upload_files
+    );
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+
+    view! {
+        // Upload files without leaving the page and copy their markdown snippets
+        <Script>
+        "
+            document.addEventListener('submit', async ev => {
+                const form = ev.target;
+                if (form.id !== 'upload_form') {
+                    return;
+                }
+
+                ev.preventDefault();
+                const status = document.getElementById('upload_status');
+                status.textContent = 'Uploading...';
+
+                const res = await fetch(form.action, {
+                    method: 'POST',
+                    headers: { 'Accept': 'application/json' },
+                    body: new FormData(form),
+                });
+
+                if (!res.ok) {
+                    status.textContent = await res.text();
+                    return;
+                }
+
+                location.reload();
+            });
+
+            document.addEventListener('click', async ev => {
+                const button = ev.target.closest('.copy_btn');
+                if (!button) {
+                    return;
                 }
-            ])
-        );
+
+                await navigator.clipboard.writeText(button.dataset.snippet);
+                button.textContent = 'Copied';
+                setTimeout(() => button.textContent = 'Copy', 1500);
+            });
+        "
+        </Script>
+        <Navbar/>
+        <article id="notes_wrapper" class="uploads_page">
+            <h1>"Uploads"</h1>
+            <form id="upload_form" method="post" action=upload_url enctype="multipart/form-data">
+                <input type="text" name="folder" placeholder="folder (optional)"/>
+                <input type="file" name="file" accept=accept multiple required/>
+                <input type="submit" value="Upload"/>
+                <span id="upload_status">{limits}</span>
+            </form>
+            <Suspense fallback=move || view! { <p>"Listing uploads..."</p> }>
+                {move || files.get().map(|files| match files {
+                    Ok(files) if files.is_empty() => view! {
+                        <p>"Nothing has been uploaded yet."</p>
+                    }.into_view(),
+                    Ok(files) => view! {
+                        <table class="dir_listing">
+                            <thead>
+                                <tr>
+                                    <th>"File"</th>
+                                    <th>"Markdown"</th>
+                                    <th>"Size"</th>
+                                    <th>"Modified"</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                {files.into_iter()
+                                    .map(|file| view! { <ResourceRow username=username.get_value() file=file/> })
+                                    .collect_view()}
+                            </tbody>
+                        </table>
+                    }.into_view(),
+                    Err(e) => view! {
+                        <p class="error">
+                        {e.to_string()
+                            .strip_prefix("error running server function: ")
+                            .unwrap_or("Failed to list uploads")
+                            .to_owned()}
+                        </p>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </article>
+    }.into_view()
+}
+
+#[component]
+pub fn ResourceRow(username: String, file: ResourceFile) -> impl IntoView {
+    let href = format!("/{username}/resources/{}", encode_path(&file.path));
+
+    view! {
+        <tr>
+            <td><a href=href target="_blank">{file.path}</a></td>
+            <td class="resource_snippet">
+                <code>{file.markdown.clone()}</code>
+                <button type="button" class="copy_btn" data-snippet=file.markdown>"Copy"</button>
+            </td>
+            <td>{format_size(file.size)}</td>
+            <td>{file.modified.map(format_timestamp)}</td>
+        </tr>
     }
 }
+
+#[component]
+pub fn Trash() -> impl IntoView {
+    let auth: AuthSession<auth::User, String, SessionSurrealPool<Client>, Surreal<Client>> =
+        expect_context();
+    let response: ResponseOptions = expect_context();
+    let ln_settings: LazyNotesSettings = expect_context();
+
+    if !auth.is_authenticated() {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let user = auth.current_user.clone().expect("User was not authenticated");
+    let params = use_params::<UserParams>();
+
+    // Users may only see their own trash
+    if !params.with(|params|
+        params.as_ref()
+            .map(|params| user.username == params.user)
+            .is_ok_and(|authenticated| authenticated))
+    {
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return view! { <Unauthorized/> };
+    }
+
+    let entries = create_blocking_resource(move || (), move |_| list_trash());
+    let retention = match ln_settings.trash_retention_days {
+        0 => "Deleted notes and folders are kept until you purge them.".to_string(),
+        1 => "Deleted notes and folders are purged after a day.".to_string(),
+        days => format!("Deleted notes and folders are purged after {days} days."),
+    };
+    // Stored so the Suspense children can be rendered more than once
+    let username = store_value(user.username.clone());
+
+    view! {
+        // Restore and purge trash entries, asking first before anything is purged
+        <Script>
+        "
+            document.addEventListener('submit', async ev => {
+                const form = ev.target;
+                const trash = form.closest('.trash_list');
+                if (!trash) {
+                    return;
+                }
+
+                ev.preventDefault();
+                if (form.dataset.confirm && !confirm(form.dataset.confirm)) {
+                    return;
+                }
+
+                const res = await fetch('/api/' + form.dataset.action, {
+                    method: 'POST',
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/x-www-form-urlencoded',
+                    },
+                    body: new URLSearchParams(new FormData(form)),
+                });
+
+                if (!res.ok) {
+                    // Server function errors are prefixed with their kind
+                    alert((await res.text()).replace(/^\\w+\\|/, ''));
+                    return;
+                }
+
+                if (form.dataset.action === 'restore_trash_entry') {
+                    const path = await res.json();
+                    location.href = '/' + trash.dataset.user + '/notes/'
+                        + path.split('/').map(encodeURIComponent).join('/');
+                } else {
+                    location.reload();
+                }
+            });
+        "
+        </Script>
+        <Navbar/>
+        <article id="notes_wrapper" class="trash_page">
+            <h1>"Trash"</h1>
+            <p class="trash_retention">{retention}</p>
+            <Suspense fallback=move || view! { <p>"Emptying the bins..."</p> }>
+                {move || entries.get().map(|entries| match entries {
+                    Ok(entries) if entries.is_empty() => view! {
+                        <p>"The trash is empty."</p>
+                    }.into_view(),
+                    Ok(entries) => view! {
+                        <table class="dir_listing trash_list" data-user=username.get_value()>
+                            <thead>
+                                <tr><th>"Path"</th><th>"Deleted"</th><th></th></tr>
+                            </thead>
+                            <tbody>
+                                {entries.into_iter()
+                                    .map(|entry| view! { <TrashRow entry=entry/> })
+                                    .collect_view()}
+                            </tbody>
+                        </table>
+                    }.into_view(),
+                    Err(e) => view! {
+                        <p class="error">
+                        {e.to_string()
+                            .strip_prefix("error running server function: ")
+                            .unwrap_or("Failed to list the trash")
+                            .to_owned()}
+                        </p>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </article>
+    }.into_view()
+}
+
+#[component]
+pub fn TrashRow(entry: TrashEntry) -> impl IntoView {
+    let class = match entry.is_dir {
+        true => "directory",
+        false => "note",
+    };
+    let path = match entry.is_dir {
+        true => format!("{}/", entry.path),
+        false => entry.path.clone(),
+    };
+
+    view! {
+        <tr class=class>
+            <td>{path}</td>
+            <td>{format_timestamp(entry.deleted)}</td>
+            <td class="trash_actions">
+                <form data-action="restore_trash_entry">
+                    <input type="hidden" name="id" value=entry.id.clone()/>
+                    <input type="submit" value="Restore"/>
+                </form>
+                <form data-action="purge_trash_entry"
+                      data-confirm=format!("Permanently delete {}?", entry.path)>
+                    <input type="hidden" name="id" value=entry.id/>
+                    <input type="submit" value="Delete forever"/>
+                </form>
+            </td>
+        </tr>
+    }
+}
+
+#[component]
+pub fn Unauthorized() -> impl IntoView {
+    view! {
+        <Navbar/>
+        <article class="no_permission">
+            <p>"You do not have permission to view this page."</p>
+        </article>
+    }
+}
+}}
+
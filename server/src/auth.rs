@@ -42,7 +42,7 @@ cfg_if!( if #[cfg(feature = "ssr")] {
             pool: Option<&Surreal<Client>>,
         ) -> Result<User, anyhow::Error> {
             let pool = pool.unwrap();
-            User::get(username, &pool)
+            User::get(username, pool)
                 .await
                 .ok_or_else(|| anyhow::anyhow!("Could not load user!"))
         }
@@ -0,0 +1,246 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::{CacheStats, RenderedNote};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// What a cached note was rendered from, telling whether it is still fresh.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Generation of the notes and resources links could resolve to
+    generation: u64,
+}
+
+impl RenderStamp {
+    pub fn new(file: &Metadata, generation: u64) -> Self {
+        Self {
+            modified: file.modified().ok(),
+            len: file.len(),
+            generation,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedNote {
+    stamp: RenderStamp,
+    note: RenderedNote,
+    /// Value of the use counter when the note was last read
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    notes: HashMap<PathBuf, CachedNote>,
+    uses: u64,
+}
+
+/// Bounded cache of rendered notes, keyed by their path on disk.
+///
+/// Once full, the least recently used note is evicted to make room.
+#[derive(Clone, Debug)]
+pub struct RenderCache {
+    capacity: usize,
+    entries: Arc<Mutex<Entries>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl RenderCache {
+    /// Creates a cache holding up to `capacity` notes, caching nothing if 0.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+
+    /// Returns the cached render of the note at `path` if it was rendered
+    /// from `stamp`, otherwise renders and caches it.
+    ///
+    /// Rendering happens without holding the lock, so notes rendered at the
+    /// same time don't wait on each other.
+    pub async fn get_or_render<E, F>(
+        &self,
+        path: &Path,
+        stamp: RenderStamp,
+        render: impl FnOnce() -> F,
+    ) -> Result<RenderedNote, E>
+    where
+        F: Future<Output = Result<RenderedNote, E>>,
+    {
+        if let Some(note) = self.get(path, &stamp) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(note);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let note = render().await?;
+        self.insert(path, stamp, note.clone());
+        Ok(note)
+    }
+
+    /// Hit and miss counts since the cache was created.
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: entries.notes.len(),
+        }
+    }
+
+    fn get(&self, path: &Path, stamp: &RenderStamp) -> Option<RenderedNote> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.uses += 1;
+        let uses = entries.uses;
+
+        let cached = entries.notes.get_mut(path).filter(|cached| cached.stamp == *stamp)?;
+        cached.last_used = uses;
+        Some(cached.note.clone())
+    }
+
+    fn insert(&self, path: &Path, stamp: RenderStamp, note: RenderedNote) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.uses += 1;
+        let last_used = entries.uses;
+
+        if !entries.notes.contains_key(path) && entries.notes.len() >= self.capacity {
+            let oldest = entries
+                .notes
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone());
+
+            if let Some(oldest) = oldest {
+                entries.notes.remove(&oldest);
+            }
+        }
+
+        entries.notes.insert(
+            path.to_path_buf(),
+            CachedNote {
+                stamp,
+                note,
+                last_used,
+            },
+        );
+    }
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::api::{CacheStats, RenderedNote};
+    use crate::cache::{RenderCache, RenderStamp};
+    use std::fs::{metadata, write};
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    async fn render(cache: &RenderCache, path: &Path, generation: u64) -> (RenderedNote, bool) {
+        let stamp = RenderStamp::new(&metadata(path).unwrap(), generation);
+        let mut rendered = false;
+        let note = cache
+            .get_or_render(path, stamp, || {
+                rendered = true;
+                async {
+                    Ok::<_, ()>(RenderedNote {
+                        html: std::fs::read_to_string(path).unwrap(),
+                        ..Default::default()
+                    })
+                }
+            })
+            .await
+            .unwrap();
+
+        (note, rendered)
+    }
+
+    #[tokio::test]
+    async fn stale_renders() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        write(&note, "# Note").unwrap();
+        let cache = RenderCache::new(8);
+
+        assert!(render(&cache, &note, 0).await.1);
+        assert!(!render(&cache, &note, 0).await.1);
+
+        // Editing the note or the notes it links to renders it again
+        write(&note, "# Edited note").unwrap();
+        let (edited, rendered) = render(&cache, &note, 0).await;
+        assert!(rendered);
+        assert_eq!(edited.html, "# Edited note");
+        assert!(render(&cache, &note, 1).await.1);
+        assert!(!render(&cache, &note, 1).await.1);
+
+        // Failed renders are not cached
+        let stamp = RenderStamp::new(&metadata(&note).unwrap(), 1);
+        let missing = dir.join("missing.md");
+        assert_eq!(
+            cache.get_or_render(&missing, stamp, || async { Err("failed") }).await,
+            Err("failed")
+        );
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                len: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn least_recently_used() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let [a, b, c] = ["a.md", "b.md", "c.md"].map(|name| dir.join(name));
+        for path in [&a, &b, &c] {
+            write(path, "# Note").unwrap();
+        }
+        let cache = RenderCache::new(2);
+
+        render(&cache, &a, 0).await;
+        render(&cache, &b, 0).await;
+        // Reading a makes b the least recently used note
+        assert!(!render(&cache, &a, 0).await.1);
+        render(&cache, &c, 0).await;
+
+        assert!(!render(&cache, &a, 0).await.1);
+        assert!(!render(&cache, &c, 0).await.1);
+        assert!(render(&cache, &b, 0).await.1);
+        assert_eq!(cache.stats().len, 2);
+    }
+
+    #[tokio::test]
+    async fn disabled_cache() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let note = dir.join("note.md");
+        write(&note, "# Note").unwrap();
+        let cache = RenderCache::new(0);
+
+        assert!(render(&cache, &note, 0).await.1);
+        assert!(render(&cache, &note, 0).await.1);
+        assert_eq!(cache.stats().len, 0);
+    }
+}
@@ -0,0 +1,796 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::{format_size, DiffLine, Revision};
+use crate::paths::{self, SymlinkPolicy};
+use std::fs::{
+    create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename, OpenOptions,
+};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many snapshots of a note are kept, and for how long.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retention {
+    /// Most snapshots kept per note, 0 keeps none
+    pub max_count: usize,
+    /// Age in seconds after which snapshots are removed, 0 keeps them forever
+    pub max_age: i64,
+}
+
+/// Unchanged lines shown around every change of a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Regions needing more edits than this are shown as replaced wholesale
+/// instead of finding the lines in common, bounding the time diffs take.
+const MAX_DIFF_EDITS: isize = 1000;
+
+/// Starts every commit in our `git log` output.
+const COMMIT_SEPARATOR: char = '\u{1e}';
+/// Separates the fields of a commit in our `git log` output.
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+/// A commit touching a note, along with the path the note had at the time.
+#[derive(Debug, PartialEq)]
+struct Commit {
+    revision: Revision,
+    path: String,
+}
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "core.quotepath=off"])
+        .env("GIT_TERMINAL_PROMPT", "0");
+    command
+}
+
+fn run(command: &mut Command) -> std::io::Result<String> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Whether `dir` is inside a git work tree. False if git isn't installed.
+pub fn is_repository(dir: &Path) -> bool {
+    run(git(dir).args(["rev-parse", "--is-inside-work-tree"]))
+        .is_ok_and(|output| output.trim() == "true")
+}
+
+/// Whether `revision` is a (possibly abbreviated) commit hash.
+///
+/// Nothing else is passed on to git, so revisions can never be mistaken for
+/// options or revision expressions.
+pub fn is_valid_revision(revision: &str) -> bool {
+    (4..=64).contains(&revision.len())
+        && revision.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Parses `git log --name-only` output in our format.
+///
+/// Commits without a file name (e.g. merges) are attributed to the path of
+/// the newer commit before them.
+fn parse_log(output: &str, path: &str) -> Vec<Commit> {
+    let mut path = path.to_string();
+
+    output
+        .split(COMMIT_SEPARATOR)
+        .filter_map(|commit| {
+            let mut lines = commit.lines();
+            let mut fields = lines.next()?.split(FIELD_SEPARATOR);
+            let (id, author, time, summary) =
+                (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+
+            if let Some(name) = lines.map(str::trim).rfind(|line| !line.is_empty()) {
+                path = name.to_string();
+            }
+
+            Some(Commit {
+                revision: Revision {
+                    id: id.to_string(),
+                    author: author.to_string(),
+                    time: time.parse().ok()?,
+                    summary: summary.to_string(),
+                },
+                path: path.clone(),
+            })
+        })
+        .collect()
+}
+
+fn commits(dir: &Path, path: &str) -> std::io::Result<Vec<Commit>> {
+    let format = format!(
+        "--format={COMMIT_SEPARATOR}%H{FIELD_SEPARATOR}%an{FIELD_SEPARATOR}%at{FIELD_SEPARATOR}%s"
+    );
+    let output = run(git(dir)
+        .args(["log", "--follow", "--relative", "--name-only", &format, "--"])
+        .arg(path))?;
+
+    Ok(parse_log(&output, path))
+}
+
+/// Lists the commits touching the note at `path` (relative to `dir`), newest first.
+pub fn log(dir: &Path, path: &str) -> std::io::Result<Vec<Revision>> {
+    Ok(commits(dir, path)?
+        .into_iter()
+        .map(|commit| commit.revision)
+        .collect())
+}
+
+/// Content of the note at `path` as of `revision`, following renames.
+///
+/// Only commits that touched the note are accepted.
+pub fn show(dir: &Path, path: &str, revision: &str) -> std::io::Result<String> {
+    if !is_valid_revision(revision) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Invalid revision"));
+    }
+
+    let commit = commits(dir, path)?
+        .into_iter()
+        .find(|commit| commit.revision.id.starts_with(revision))
+        .ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+
+    // `./` makes the path relative to `dir` rather than the root of the repository
+    run(git(dir)
+        .arg("show")
+        .arg(format!("{}:./{}", commit.revision.id, commit.path)))
+}
+
+/// Commits the note at `path` with `username` as author, unless it is unchanged.
+pub fn commit(dir: &Path, path: &str, username: &str) -> std::io::Result<()> {
+    run(git(dir).args(["add", "--"]).arg(path))?;
+
+    // Nothing staged for this note
+    if run(git(dir).args(["diff", "--cached", "--quiet", "--"]).arg(path)).is_ok() {
+        return Ok(());
+    }
+
+    run(git(dir)
+        .arg("-c")
+        .arg(format!("user.name={username}"))
+        .arg("-c")
+        .arg(format!("user.email={username}@lazy-notes"))
+        // There is nobody around to unlock a signing key
+        .args(["-c", "commit.gpgsign=false"])
+        .args(["commit", "--quiet", "-m", &format!("Update {path}"), "--"])
+        .arg(path))
+    .map(|_| ())
+}
+
+/// Snapshot store of a user, for notes not kept in git.
+pub fn snapshots_path(data_dir: &str, username: &str) -> PathBuf {
+    PathBuf::from(format!("{data_dir}/{username}/.history"))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Whether `id` has the `{seconds}-{counter}` form of snapshot ids.
+pub fn is_valid_snapshot_id(id: &str) -> bool {
+    id.split_once('-').is_some_and(|(secs, counter)| {
+        [secs, counter]
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// Folder holding the snapshots of the note at `path`.
+///
+/// Lazy Notes never creates symlinks in the history, so none are followed.
+fn snapshot_dir(history_dir: &Path, path: &str) -> std::io::Result<PathBuf> {
+    paths::resolve(history_dir, path, SymlinkPolicy::Deny)
+}
+
+/// Time and counter of a snapshot, used for sorting.
+fn snapshot_key(id: &str) -> (i64, u64) {
+    id.split_once('-')
+        .and_then(|(secs, counter)| Some((secs.parse().ok()?, counter.parse().ok()?)))
+        .unwrap_or_default()
+}
+
+/// Ids of the snapshots of a note, newest first.
+fn snapshot_ids(dir: &Path) -> Vec<String> {
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut ids: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".md")
+                .filter(|id| is_valid_snapshot_id(id))
+                .map(String::from)
+        })
+        .collect();
+
+    ids.sort_by_key(|id| std::cmp::Reverse(snapshot_key(id)));
+    ids
+}
+
+/// Stores `content` as the newest snapshot of the note at `path`, then
+/// removes the snapshots falling outside `retention`.
+///
+/// Nothing is stored if the newest snapshot already has this content.
+pub fn save_snapshot(
+    history_dir: &Path,
+    path: &str,
+    content: &str,
+    retention: Retention,
+) -> std::io::Result<()> {
+    if retention.max_count == 0 {
+        return Ok(());
+    }
+
+    let dir = snapshot_dir(history_dir, path)?;
+    let unchanged = snapshot_ids(&dir).first().is_some_and(|id| {
+        read_to_string(dir.join(format!("{id}.md"))).is_ok_and(|latest| latest == content)
+    });
+    if unchanged {
+        return Ok(());
+    }
+
+    create_dir_all(&dir)?;
+    let now = now();
+    let mut counter = 0;
+    let mut file = loop {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(format!("{now}-{counter}.md")));
+
+        match file {
+            Ok(file) => break file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e),
+        }
+    };
+    file.write_all(content.as_bytes())?;
+
+    prune_snapshots(&dir, retention, now);
+    Ok(())
+}
+
+/// Removes the snapshots in `dir` beyond the newest `max_count` or older than `max_age`.
+fn prune_snapshots(dir: &Path, retention: Retention, now: i64) {
+    for (i, id) in snapshot_ids(dir).into_iter().enumerate() {
+        let expired = retention.max_age > 0 && now - snapshot_key(&id).0 > retention.max_age;
+        if i >= retention.max_count || expired {
+            let _ = remove_file(dir.join(format!("{id}.md")));
+        }
+    }
+}
+
+/// Lists the snapshots of the note at `path`, newest first.
+pub fn list_snapshots(history_dir: &Path, path: &str, username: &str) -> Vec<Revision> {
+    let Ok(dir) = snapshot_dir(history_dir, path) else {
+        return Vec::new();
+    };
+
+    snapshot_ids(&dir)
+        .into_iter()
+        .map(|id| {
+            let size = dir.join(format!("{id}.md")).metadata().map_or(0, |meta| meta.len());
+
+            Revision {
+                time: snapshot_key(&id).0,
+                id,
+                author: username.to_string(),
+                summary: format!("Saved ({})", format_size(size)),
+            }
+        })
+        .collect()
+}
+
+/// Moves the snapshots of the note or folder at `from` to `to`, so renamed
+/// notes keep their history.
+pub fn move_snapshots(history_dir: &Path, from: &str, to: &str) -> std::io::Result<()> {
+    put_snapshots(history_dir, &snapshot_dir(history_dir, from)?, to)
+}
+
+/// Moves the snapshots of the note or folder at `path` out of the history to
+/// `dir`, e.g. into the trash along with the note.
+pub fn take_snapshots(history_dir: &Path, path: &str, dir: &Path) -> std::io::Result<()> {
+    match rename(snapshot_dir(history_dir, path)?, dir) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Makes the snapshots in `dir` those of the note or folder at `to`.
+pub fn put_snapshots(history_dir: &Path, dir: &Path, to: &str) -> std::io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    // Anything left at the destination belonged to a note deleted from there
+    let to = snapshot_dir(history_dir, to)?;
+    remove_snapshot_dir(&to)?;
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)?;
+    }
+    rename(dir, to)
+}
+
+fn remove_snapshot_dir(dir: &Path) -> std::io::Result<()> {
+    match remove_dir_all(dir) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Content of a snapshot of the note at `path`.
+pub fn read_snapshot(history_dir: &Path, path: &str, id: &str) -> std::io::Result<String> {
+    if !is_valid_snapshot_id(id) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Invalid revision"));
+    }
+
+    read_to_string(snapshot_dir(history_dir, path)?.join(format!("{id}.md")))
+}
+
+/// Line by line diff from `old` to `new`, with long unchanged runs collapsed.
+///
+/// Uses Myers' algorithm in linear space, so large notes with few changes are
+/// compared quickly.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut lines = Vec::new();
+    diff_region(&old, &new, &mut lines);
+    collapse_unchanged(lines)
+}
+
+/// Appends the diff from `old` to `new` to `lines`.
+fn diff_region(old: &[&str], new: &[&str], lines: &mut Vec<DiffLine>) {
+    // Only the region between the common prefix and suffix needs comparing
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let removed = &old[prefix..old.len() - suffix];
+    let added = &new[prefix..new.len() - suffix];
+
+    lines.extend(old[..prefix].iter().map(|line| DiffLine::Unchanged(line.to_string())));
+
+    match middle_snake(removed, added) {
+        Some((x, y)) => {
+            diff_region(&removed[..x], &added[..y], lines);
+            diff_region(&removed[x..], &added[y..], lines);
+        }
+        None => {
+            lines.extend(removed.iter().map(|line| DiffLine::Removed(line.to_string())));
+            lines.extend(added.iter().map(|line| DiffLine::Added(line.to_string())));
+        }
+    }
+
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Unchanged(line.to_string())),
+    );
+}
+
+/// Finds where a shortest edit script from `old` to `new` crosses its middle,
+/// searching from both ends at once, so both halves can be diffed on their own.
+///
+/// Returns `None` if the lines have nothing in common or too many edits are
+/// needed. Expects the lines to differ at both ends.
+fn middle_snake(old: &[&str], new: &[&str]) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let length = 2 * max_d + 2;
+    let delta = n - m;
+    // Paths overlap on the forward search if delta is odd, on the backward one otherwise
+    let front = delta % 2 != 0;
+
+    // Furthest x reached on every diagonal, from the start and from the end
+    let mut forward = vec![-1; length as usize];
+    let mut backward = vec![-1; length as usize];
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+
+    let split = |x: isize, y: isize| {
+        let inside = (x, y) != (0, 0) && (x, y) != (n, m);
+        inside.then_some((x as usize, y as usize))
+    };
+
+    // Diagonals running off the edges are skipped from then on
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+    for d in 0..max_d.min(MAX_DIFF_EDITS) {
+        for k1 in (-d + k1_start..=d - k1_end).step_by(2) {
+            let i = (offset + k1) as usize;
+            let mut x1 = match k1 == -d || (k1 != d && forward[i - 1] < forward[i + 1]) {
+                true => forward[i + 1],
+                false => forward[i - 1] + 1,
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && old[x1 as usize] == new[y1 as usize] {
+                (x1, y1) = (x1 + 1, y1 + 1);
+            }
+            forward[i] = x1;
+
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let j = offset + delta - k1;
+                let reached = (0..length).contains(&j) && backward[j as usize] != -1;
+                if reached && x1 >= n - backward[j as usize] {
+                    return split(x1, y1);
+                }
+            }
+        }
+
+        for k2 in (-d + k2_start..=d - k2_end).step_by(2) {
+            let i = (offset + k2) as usize;
+            let mut x2 = match k2 == -d || (k2 != d && backward[i - 1] < backward[i + 1]) {
+                true => backward[i + 1],
+                false => backward[i - 1] + 1,
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && old[(n - x2 - 1) as usize] == new[(m - y2 - 1) as usize] {
+                (x2, y2) = (x2 + 1, y2 + 1);
+            }
+            backward[i] = x2;
+
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let j = offset + delta - k2;
+                let reached = (0..length).contains(&j) && forward[j as usize] != -1;
+                if reached && forward[j as usize] >= n - x2 {
+                    let x1 = forward[j as usize];
+                    return split(x1, offset + x1 - j);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Replaces unchanged lines further than `DIFF_CONTEXT` lines from a change
+/// with a `Skipped` marker.
+fn collapse_unchanged(lines: Vec<DiffLine>) -> Vec<DiffLine> {
+    let mut collapsed = Vec::with_capacity(lines.len());
+    let mut lines = lines.into_iter().peekable();
+
+    while let Some(line) = lines.next() {
+        let DiffLine::Unchanged(text) = line else {
+            collapsed.push(line);
+            continue;
+        };
+
+        let mut run = vec![text];
+        while let Some(DiffLine::Unchanged(text)) =
+            lines.next_if(|line| matches!(line, DiffLine::Unchanged(_)))
+        {
+            run.push(text);
+        }
+
+        let before = if collapsed.is_empty() { 0 } else { DIFF_CONTEXT };
+        let after = if lines.peek().is_none() { 0 } else { DIFF_CONTEXT };
+        if run.len() <= before + after {
+            collapsed.extend(run.into_iter().map(DiffLine::Unchanged));
+            continue;
+        }
+
+        let skipped = run.len() - before - after;
+        let mut run = run.into_iter();
+        collapsed.extend(run.by_ref().take(before).map(DiffLine::Unchanged));
+        collapsed.push(DiffLine::Skipped(skipped));
+        collapsed.extend(run.skip(skipped).map(DiffLine::Unchanged));
+    }
+
+    collapsed
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::api::DiffLine::{self, Added, Removed, Skipped, Unchanged};
+    use crate::history::{
+        commit, diff_lines, diff_region, is_repository, is_valid_revision, is_valid_snapshot_id,
+        list_snapshots, log, move_snapshots, parse_log, prune_snapshots, put_snapshots,
+        read_snapshot, save_snapshot, show, snapshot_ids, take_snapshots, Retention,
+    };
+    use std::fs::{create_dir_all, write};
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn unchanged(text: &str) -> DiffLine {
+        Unchanged(text.to_string())
+    }
+
+    #[test]
+    fn revisions() {
+        assert!(is_valid_revision("3f2a9c1"));
+        assert!(is_valid_revision(&"a".repeat(40)));
+        assert!(!is_valid_revision("abc"));
+        assert!(!is_valid_revision("HEAD"));
+        assert!(!is_valid_revision("3F2A9C1"));
+        assert!(!is_valid_revision("--output=x"));
+        assert!(!is_valid_revision("3f2a9c1~1"));
+        assert!(!is_valid_revision(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn log_parsing() {
+        let output = "\u{1e}bbbb\u{1f}alice\u{1f}200\u{1f}Rename plan\n\nprojects/plan.md\n\
+                      \u{1e}cccc\u{1f}bob\u{1f}150\u{1f}Merge branch\n\
+                      \u{1e}aaaa\u{1f}alice\u{1f}100\u{1f}Add plan\n\nplan.md\n";
+        let commits = parse_log(output, "projects/plan.md");
+
+        assert_eq!(commits.len(), 3);
+        assert_eq!(commits[0].revision.id, "bbbb");
+        assert_eq!(commits[0].revision.author, "alice");
+        assert_eq!(commits[0].revision.time, 200);
+        assert_eq!(commits[0].revision.summary, "Rename plan");
+        assert_eq!(commits[1].path, "projects/plan.md");
+        assert_eq!(commits[2].path, "plan.md");
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(
+            diff_lines("a\nb\nc", "a\nB\nc\nd"),
+            vec![
+                unchanged("a"),
+                Removed("b".into()),
+                Added("B".into()),
+                unchanged("c"),
+                Added("d".into()),
+            ]
+        );
+        assert_eq!(diff_lines("same\nlines", "same\nlines"), vec![Skipped(2)]);
+    }
+
+    #[test]
+    fn minimal_diffs() {
+        // Lines drawn from a small alphabet so the notes have plenty in common
+        let mut seed = 7u32;
+        let mut note = |len: usize| -> Vec<String> {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    ((seed >> 16) % 4).to_string()
+                })
+                .collect()
+        };
+
+        for (old_len, new_len) in [(0, 3), (5, 0), (8, 8), (12, 7), (3, 20), (40, 35)] {
+            let (old, new) = (note(old_len), note(new_len));
+            let old: Vec<&str> = old.iter().map(String::as_str).collect();
+            let new: Vec<&str> = new.iter().map(String::as_str).collect();
+
+            let mut lines = Vec::new();
+            diff_region(&old, &new, &mut lines);
+
+            // Both notes can be read back from the diff
+            let side = |keep: fn(&DiffLine) -> Option<&String>| -> Vec<&str> {
+                lines.iter().filter_map(keep).map(String::as_str).collect()
+            };
+            assert_eq!(
+                side(|line| match line {
+                    Unchanged(text) | Removed(text) => Some(text),
+                    _ => None,
+                }),
+                old
+            );
+            assert_eq!(
+                side(|line| match line {
+                    Unchanged(text) | Added(text) => Some(text),
+                    _ => None,
+                }),
+                new
+            );
+
+            // And as many lines as possible are kept
+            let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    common[i][j] = match old[i] == new[j] {
+                        true => common[i + 1][j + 1] + 1,
+                        false => common[i + 1][j].max(common[i][j + 1]),
+                    };
+                }
+            }
+            let unchanged = lines
+                .iter()
+                .filter(|line| matches!(line, Unchanged(_)))
+                .count();
+            assert_eq!(unchanged, common[0][0]);
+        }
+    }
+
+    #[test]
+    fn large_diffs() {
+        let old: Vec<String> = (0..20_000).map(|i| format!("line {i}")).collect();
+        let mut new = old.clone();
+        new[5_000] = "changed".to_string();
+        new.insert(15_000, "inserted".to_string());
+
+        let diff = diff_lines(&old.join("\n"), &new.join("\n"));
+        let changes = diff
+            .iter()
+            .filter(|line| matches!(line, Added(_) | Removed(_)))
+            .count();
+        assert_eq!(changes, 3);
+    }
+
+    #[test]
+    fn diff_context() {
+        let old: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new[9] = "ten".to_string();
+
+        assert_eq!(
+            diff_lines(&old.join("\n"), &new.join("\n")),
+            vec![
+                Skipped(6),
+                unchanged("7"),
+                unchanged("8"),
+                unchanged("9"),
+                Removed("10".into()),
+                Added("ten".into()),
+                unchanged("11"),
+                unchanged("12"),
+                unchanged("13"),
+                Skipped(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn git_history() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+
+        let temp = tempdir().unwrap();
+        let root = temp.path().to_path_buf();
+        let notes = root.join("notes");
+        create_dir_all(&notes).unwrap();
+
+        Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .arg("init")
+            .output()
+            .unwrap();
+        assert!(is_repository(&notes));
+
+        write(notes.join("plan.md"), "# Plan\n").unwrap();
+        commit(&notes, "plan.md", "alice").unwrap();
+        write(notes.join("plan.md"), "# Plan\n\n- Write tests\n").unwrap();
+        commit(&notes, "plan.md", "bob").unwrap();
+        // Unchanged notes aren't committed again
+        commit(&notes, "plan.md", "bob").unwrap();
+
+        let revisions = log(&notes, "plan.md").unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].author, "bob");
+        assert_eq!(revisions[0].summary, "Update plan.md");
+        assert_eq!(revisions[1].author, "alice");
+
+        let first = &revisions[1].id;
+        assert_eq!(show(&notes, "plan.md", first).unwrap(), "# Plan\n");
+        assert_eq!(show(&notes, "plan.md", &first[..7]).unwrap(), "# Plan\n");
+        assert!(show(&notes, "plan.md", "HEAD").is_err());
+        assert!(show(&notes, "plan.md", "0000000").is_err());
+    }
+
+    #[test]
+    fn snapshot_ids_are_validated() {
+        assert!(is_valid_snapshot_id("1700000000-0"));
+        assert!(is_valid_snapshot_id("1700000000-12"));
+        assert!(!is_valid_snapshot_id("1700000000"));
+        assert!(!is_valid_snapshot_id("-1"));
+        assert!(!is_valid_snapshot_id("1700000000-0/../../x"));
+        assert!(!is_valid_snapshot_id("../1-1"));
+    }
+
+    #[test]
+    fn snapshots() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let retention = Retention {
+            max_count: 3,
+            max_age: 0,
+        };
+
+        for content in ["one", "two", "two", "three", "four"] {
+            save_snapshot(&dir, "projects/plan.md", content, retention).unwrap();
+        }
+
+        // Repeated content is stored once, and only the newest three are kept
+        let revisions = list_snapshots(&dir, "projects/plan.md", "alice");
+        assert_eq!(revisions.len(), 3);
+        assert_eq!(revisions[0].author, "alice");
+        assert_eq!(
+            read_snapshot(&dir, "projects/plan.md", &revisions[0].id).unwrap(),
+            "four"
+        );
+        assert_eq!(
+            read_snapshot(&dir, "projects/plan.md", &revisions[2].id).unwrap(),
+            "two"
+        );
+
+        assert!(read_snapshot(&dir, "projects/plan.md", "../plan").is_err());
+        assert!(save_snapshot(&dir, "../plan.md", "x", retention).is_err());
+        assert!(list_snapshots(&dir, "other.md", "alice").is_empty());
+    }
+
+    #[test]
+    fn moved_snapshots() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let retention = Retention {
+            max_count: 3,
+            max_age: 0,
+        };
+        save_snapshot(&dir, "projects/plan.md", "plan", retention).unwrap();
+        save_snapshot(&dir, "archive/plan.md", "old plan", retention).unwrap();
+        save_snapshot(&dir, "todo.md", "todo", retention).unwrap();
+
+        // Folders take the snapshots of their notes along
+        move_snapshots(&dir, "projects", "archive").unwrap();
+        let revisions = list_snapshots(&dir, "archive/plan.md", "alice");
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(
+            read_snapshot(&dir, "archive/plan.md", &revisions[0].id).unwrap(),
+            "plan"
+        );
+        assert!(list_snapshots(&dir, "projects/plan.md", "alice").is_empty());
+
+        move_snapshots(&dir, "missing.md", "todo.md").unwrap();
+        assert_eq!(list_snapshots(&dir, "todo.md", "alice").len(), 1);
+        assert!(move_snapshots(&dir, "todo.md", "../todo.md").is_err());
+
+        // Snapshots can be kept elsewhere and put back
+        let trash = tempdir().unwrap();
+        let kept = trash.path().join("todo.md");
+        take_snapshots(&dir, "todo.md", &kept).unwrap();
+        take_snapshots(&dir, "todo.md", &kept).unwrap();
+        assert!(list_snapshots(&dir, "todo.md", "alice").is_empty());
+        put_snapshots(&dir, &kept, "todo.md").unwrap();
+        assert_eq!(list_snapshots(&dir, "todo.md", "alice").len(), 1);
+    }
+
+    #[test]
+    fn snapshot_expiry() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        for id in ["100-0", "200-0", "300-0", "300-1"] {
+            write(dir.join(format!("{id}.md")), id).unwrap();
+        }
+
+        let retention = Retention {
+            max_count: 10,
+            max_age: 150,
+        };
+        prune_snapshots(&dir, retention, 400);
+        assert_eq!(snapshot_ids(&dir), vec!["300-1", "300-0"]);
+    }
+}
@@ -0,0 +1,1350 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::{
+    decode_path, encode_path, Backlink, NoteMetadata, SearchHit, SnippetPart, TagCount,
+    TaggedNote,
+};
+use crate::markdown::{
+    extract_math, heading_ids, literal_ranges, math_source, split_frontmatter, strip_math,
+    wiki_link_regex, wiki_link_text, LinkResolver,
+};
+use crate::paths::{self, SymlinkPolicy};
+use crate::sanitizer::SanitizerSettings;
+use crate::watcher::NoteWatcher;
+use pulldown_cmark::{utils::TextMergeStream, Event, LinkType, Options, Parser, Tag, TagEnd};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, read_dir, read_to_string, Metadata};
+use std::iter::once;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// Maximum number of hits returned by a search.
+const MAX_HITS: usize = 50;
+
+/// Number of characters shown on either side of the first match in a snippet.
+const SNIPPET_RADIUS: usize = 80;
+
+/// Maximum number of characters kept as the context of a link.
+const CONTEXT_LENGTH: usize = 300;
+
+/// In-memory index of the notes of every user.
+///
+/// Indexes are built lazily and re-synced with the disk when the watcher
+/// reports a change, so notes edited locally are picked up on the next query.
+/// Without a watcher they are re-synced whenever they are used.
+///
+/// Syncing reads the disk, so the index should be used from blocking tasks.
+#[derive(Clone, Debug, Default)]
+pub struct NotesIndex {
+    users: Arc<Mutex<HashMap<String, Arc<Mutex<UserIndex>>>>>,
+    sanitizer: Arc<SanitizerSettings>,
+    symlink_policy: SymlinkPolicy,
+    watcher: Option<NoteWatcher>,
+}
+
+/// The indexed notes of a single user, keyed by path relative to `notes/`.
+#[derive(Debug, Default)]
+pub struct UserIndex {
+    pub notes: HashMap<String, IndexedNote>,
+    /// Whether the notes were read from disk at least once
+    synced: bool,
+    /// Changes reported by the watcher since the last sync
+    changes: Option<broadcast::Receiver<String>>,
+    /// Paths of the resources, listed again after any change
+    resources: Option<Vec<String>>,
+    /// Fingerprint of the notes and resources links could resolve to
+    links: u64,
+    /// Bumped whenever the notes and resources links resolve to change
+    generation: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexedNote {
+    modified: Option<SystemTime>,
+    len: u64,
+    pub title: String,
+    pub metadata: NoteMetadata,
+    /// Lowercase tags from the frontmatter and `#tags` in the text
+    pub tags: Vec<String>,
+    pub sections: Vec<Section>,
+    pub links: Vec<OutgoingLink>,
+}
+
+/// A link from one note to another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutgoingLink {
+    pub target: LinkTarget,
+    /// Sentence the link appears in
+    pub context: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkTarget {
+    /// Path of the linked note relative to `notes/`
+    Path(String),
+    /// Target of a wiki link, resolved once the whole vault is known
+    Wiki(String),
+}
+
+/// Text of a note between two headings.
+///
+/// The first section holds any text before the first heading and has no
+/// heading of its own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Section {
+    pub heading: Option<String>,
+    pub anchor: Option<String>,
+    pub text: String,
+}
+
+impl NotesIndex {
+    /// Creates an empty index of notes rendered with `sanitizer`, following
+    /// symlinks as `symlink_policy` allows and re-syncing the notes of a user
+    /// when `watcher` sees them change.
+    pub fn new(
+        sanitizer: SanitizerSettings,
+        symlink_policy: SymlinkPolicy,
+        watcher: NoteWatcher,
+    ) -> Self {
+        Self {
+            users: Arc::default(),
+            sanitizer: Arc::new(sanitizer),
+            symlink_policy,
+            watcher: Some(watcher),
+        }
+    }
+
+    /// The sanitizer settings notes are rendered with.
+    pub fn sanitizer(&self) -> &SanitizerSettings {
+        &self.sanitizer
+    }
+
+    /// Syncs the index of a user with the notes in `notes_dir` if they changed
+    /// and runs `f` on it.
+    ///
+    /// Only the index of this user is locked while syncing.
+    pub fn with_user<T>(
+        &self,
+        username: &str,
+        notes_dir: &Path,
+        f: impl FnOnce(&UserIndex) -> T,
+    ) -> T {
+        self.with_synced(username, notes_dir, None, f)
+    }
+
+    /// Syncs the index of a user like [`Self::with_user`], also listing the
+    /// resources in `resources_dir` when links have to be resolved.
+    fn with_synced<T>(
+        &self,
+        username: &str,
+        notes_dir: &Path,
+        resources_dir: Option<&Path>,
+        f: impl FnOnce(&UserIndex) -> T,
+    ) -> T {
+        let index = {
+            let mut users = self.users.lock().unwrap_or_else(PoisonError::into_inner);
+            users.entry(username.to_string()).or_default().clone()
+        };
+
+        let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+        if index.changes.is_none() {
+            // Watch before syncing so nothing changing in between is missed
+            index.changes = self
+                .watcher
+                .as_ref()
+                .and_then(|watcher| watcher.subscribe(username).ok());
+            index.synced = false;
+        }
+        if index.take_changes() {
+            index.sync(username, notes_dir, &self.sanitizer, self.symlink_policy);
+        }
+        if let Some(resources_dir) = resources_dir.filter(|_| index.resources.is_none()) {
+            let mut resources = Vec::new();
+            collect_files(resources_dir, self.symlink_policy, &mut |path, _| {
+                resources.push(path)
+            });
+            index.update_links(username, resources);
+        }
+        f(&index)
+    }
+
+    /// Re-syncs the notes and resources of a user on next use, for changes
+    /// made by Lazy Notes itself which the watcher may not have reported yet.
+    pub fn invalidate(&self, username: &str) {
+        let users = self.users.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = users.get(username) {
+            let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+            index.synced = false;
+            index.resources = None;
+        }
+    }
+
+    /// Builds a resolver for wiki links from the user's notes and resources.
+    pub fn link_resolver(
+        &self,
+        username: &str,
+        notes_dir: &Path,
+        resources_dir: &Path,
+    ) -> LinkResolver {
+        self.with_synced(username, notes_dir, Some(resources_dir), |index| {
+            let resources = index.resources.clone().unwrap_or_default();
+            index
+                .link_resolver(username, resources)
+                .with_id_prefix(&self.sanitizer.id_prefix)
+        })
+    }
+
+    /// Generation of the notes and resources wiki links of a user resolve to.
+    ///
+    /// It changes whenever notes or resources are created, removed or given
+    /// other aliases, so notes rendered with an older generation are stale.
+    pub fn generation(&self, username: &str, notes_dir: &Path, resources_dir: &Path) -> u64 {
+        self.with_synced(username, notes_dir, Some(resources_dir), |index| index.generation)
+    }
+
+    /// Lists the notes linking to the note at `path`.
+    pub fn backlinks(&self, username: &str, notes_dir: &Path, path: &str) -> Vec<Backlink> {
+        self.with_user(username, notes_dir, |index| {
+            let resolver = index.link_resolver(username, []);
+            let mut backlinks: Vec<Backlink> = Vec::new();
+
+            for (source, note) in &index.notes {
+                for link in &note.links {
+                    let target = match &link.target {
+                        LinkTarget::Path(target) => Some(target.clone()),
+                        LinkTarget::Wiki(target) => {
+                            resolver.resolve_note(target).map(|target| format!("{target}.md"))
+                        }
+                    };
+
+                    if target.as_deref() != Some(path) {
+                        continue;
+                    }
+
+                    let backlink = Backlink {
+                        path: source.clone(),
+                        title: note.title.clone(),
+                        context: link.context.clone(),
+                    };
+
+                    if !backlinks.contains(&backlink) {
+                        backlinks.push(backlink);
+                    }
+                }
+            }
+
+            backlinks.sort_by(|a, b| a.path.cmp(&b.path));
+            backlinks
+        })
+    }
+
+    /// Searches a user's notes, returning the best matching sections first.
+    pub fn search(&self, username: &str, notes_dir: &Path, query: &str) -> Vec<SearchHit> {
+        self.with_user(username, notes_dir, |index| index.search(query))
+    }
+
+    /// Lists every tag of a user's notes along with how many notes carry it.
+    pub fn tags(&self, username: &str, notes_dir: &Path) -> Vec<TagCount> {
+        self.with_user(username, notes_dir, UserIndex::tag_counts)
+    }
+
+    /// Lists the notes carrying `tag` or one of its nested tags.
+    pub fn tagged_notes(&self, username: &str, notes_dir: &Path, tag: &str) -> Vec<TaggedNote> {
+        self.with_user(username, notes_dir, |index| index.tagged_notes(tag))
+    }
+}
+
+impl UserIndex {
+    /// Drains the changes reported since the last sync, returning whether the
+    /// notes have to be synced again.
+    fn take_changes(&mut self) -> bool {
+        let Some(changes) = &mut self.changes else {
+            return true;
+        };
+
+        let mut changed = !self.synced;
+        loop {
+            match changes.try_recv() {
+                Ok(_) | Err(TryRecvError::Lagged(_)) => changed = true,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => {
+                    self.changes = None;
+                    return true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Keeps the listed resources, bumping the generation if links could now
+    /// resolve differently.
+    fn update_links(&mut self, username: &str, resources: Vec<String>) {
+        let links = self.link_resolver(username, resources.iter().cloned()).fingerprint();
+        if links != self.links {
+            self.links = links;
+            self.generation += 1;
+        }
+        self.resources = Some(resources);
+    }
+
+    /// Builds a resolver for wiki links to these notes and their aliases.
+    fn link_resolver(
+        &self,
+        username: &str,
+        resources: impl IntoIterator<Item = String>,
+    ) -> LinkResolver {
+        LinkResolver::new(username, self.notes.keys().cloned(), resources).with_aliases(
+            self.notes
+                .iter()
+                .map(|(path, note)| (path.as_str(), note.metadata.aliases.as_slice())),
+        )
+    }
+
+    /// Re-indexes notes that changed on disk and drops notes that were removed.
+    ///
+    /// Notes are only read again if their modification time or size changed.
+    fn sync(
+        &mut self,
+        username: &str,
+        notes_dir: &Path,
+        sanitizer: &SanitizerSettings,
+        policy: SymlinkPolicy,
+    ) {
+        let mut files = Vec::new();
+        collect_files(notes_dir, policy, &mut |path, meta| {
+            if path.ends_with(".md") {
+                files.push((path, meta.modified().ok(), meta.len()));
+            }
+        });
+
+        let mut seen = HashSet::with_capacity(files.len());
+        for (path, modified, len) in files {
+            let unchanged = self
+                .notes
+                .get(&path)
+                .is_some_and(|note| note.modified == modified && note.len == len);
+
+            if !unchanged {
+                match read_to_string(notes_dir.join(&path)) {
+                    Ok(markdown) => {
+                        let note =
+                            index_note(username, &path, &markdown, modified, len, sanitizer);
+                        self.notes.insert(path.clone(), note);
+                    }
+                    Err(_) => continue,
+                }
+            }
+
+            seen.insert(path);
+        }
+
+        self.notes.retain(|path, _| seen.contains(path));
+        self.synced = true;
+        // Links are checked again on next use, as notes may have been added
+        self.resources = None;
+    }
+
+    /// Counts notes per tag, with nested tags like `project/alpha` also
+    /// counting towards their parents.
+    fn tag_counts(&self) -> Vec<TagCount> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+
+        for note in self.notes.values() {
+            let tags: HashSet<&str> =
+                note.tags.iter().flat_map(|tag| tag_and_parents(tag)).collect();
+            for tag in tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+
+        let mut counts: Vec<TagCount> = counts
+            .into_iter()
+            .map(|(tag, count)| TagCount {
+                tag: tag.to_string(),
+                count,
+            })
+            .collect();
+        counts.sort_by(|a, b| a.tag.cmp(&b.tag));
+        counts
+    }
+
+    /// Lists the notes carrying a tag, most recently modified first.
+    fn tagged_notes(&self, tag: &str) -> Vec<TaggedNote> {
+        let tag = tag.trim().trim_start_matches('#').trim_matches('/').to_lowercase();
+
+        let mut notes: Vec<TaggedNote> = self
+            .notes
+            .iter()
+            .filter(|(_, note)| {
+                note.tags
+                    .iter()
+                    .any(|t| tag_and_parents(t).any(|parent| parent == tag))
+            })
+            .map(|(path, note)| TaggedNote {
+                path: path.clone(),
+                title: note.title.clone(),
+                modified: note
+                    .modified
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .and_then(|duration| i64::try_from(duration.as_secs()).ok()),
+            })
+            .collect();
+
+        notes.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.path.cmp(&b.path)));
+        notes
+    }
+
+    fn search(&self, query: &str) -> Vec<SearchHit> {
+        let terms: Vec<&str> = query.split_whitespace().take(16).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let matchers: Vec<Regex> = terms
+            .iter()
+            .filter_map(|term| {
+                RegexBuilder::new(&regex::escape(term))
+                    .case_insensitive(true)
+                    .build()
+                    .ok()
+            })
+            .collect();
+
+        let mut hits = Vec::new();
+        for (path, note) in &self.notes {
+            for section in &note.sections {
+                let heading = section.heading.as_deref().unwrap_or_default();
+                let mut score = 0;
+                let mut all_found = true;
+
+                for matcher in &matchers {
+                    let in_text = matcher.find_iter(&section.text).count();
+                    let in_heading = matcher.find_iter(heading).count();
+                    let in_title = matcher.find_iter(&note.title).count();
+
+                    if in_text + in_heading + in_title == 0 {
+                        all_found = false;
+                        break;
+                    }
+
+                    score += in_text + 5 * in_heading + 10 * in_title;
+                }
+
+                if !all_found {
+                    continue;
+                }
+
+                hits.push(SearchHit {
+                    path: path.clone(),
+                    title: note.title.clone(),
+                    heading: section.heading.clone(),
+                    anchor: section.anchor.clone(),
+                    snippet: build_snippet(&section.text, &matchers),
+                    score,
+                });
+            }
+        }
+
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        hits.truncate(MAX_HITS);
+        hits
+    }
+}
+
+/// Recursively visits every file below `root`, skipping hidden entries.
+///
+/// Symlinks are only followed where `policy` allows and every folder is
+/// listed once, so links back up the tree can't recurse forever. Paths passed
+/// to `visit` are relative to `root`.
+fn collect_files(root: &Path, policy: SymlinkPolicy, visit: &mut impl FnMut(String, Metadata)) {
+    let mut visited = HashSet::new();
+    collect_files_below(root, root, "", policy, &mut visited, visit);
+}
+
+fn collect_files_below(
+    root: &Path,
+    dir: &Path,
+    prefix: &str,
+    policy: SymlinkPolicy,
+    visited: &mut HashSet<PathBuf>,
+    visit: &mut impl FnMut(String, Metadata),
+) {
+    let Ok(real_dir) = dir.canonicalize() else {
+        return;
+    };
+    if !visited.insert(real_dir) {
+        return;
+    }
+
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = match prefix.is_empty() {
+            true => name.clone(),
+            false => format!("{prefix}/{name}"),
+        };
+
+        let Ok(mut meta) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if meta.file_type().is_symlink() {
+            if paths::resolve(root, &path, policy).is_err() {
+                continue;
+            }
+            // Dangling symlinks have nothing to visit
+            meta = match metadata(entry.path()) {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+        }
+
+        if meta.is_dir() {
+            collect_files_below(root, &entry.path(), &path, policy, visited, visit);
+        } else {
+            visit(path, meta);
+        }
+    }
+}
+
+/// Splits a note into its sections and collects the links it contains.
+///
+/// The note is parsed the way it is rendered, math taken out and headings
+/// given ids, so that anchors match the ids on the rendered page.
+fn index_note(
+    username: &str,
+    path: &str,
+    markdown: &str,
+    modified: Option<SystemTime>,
+    len: u64,
+    sanitizer: &SanitizerSettings,
+) -> IndexedNote {
+    let (metadata, markdown) = split_frontmatter(markdown);
+    let (markdown, math, _) = extract_math(markdown);
+    let parser = Parser::new_ext(&markdown, Options::all());
+    let events = heading_ids(parser, &sanitizer.id_prefix);
+
+    let mut sections = vec![Section::default()];
+    let mut heading = String::new();
+    let mut links = Vec::new();
+    let mut in_heading = false;
+    let mut in_code_block = false;
+
+    let mut tags: Vec<String> = metadata.tags.iter().map(|tag| tag.to_lowercase()).collect();
+
+    // Text of the current block and the links found in it so far
+    let mut block = String::new();
+    let mut block_links: Vec<(usize, LinkTarget)> = Vec::new();
+
+    for event in TextMergeStream::new(events.into_iter()) {
+        let is_code = matches!(event, Event::Code(_));
+
+        match event {
+            Event::Start(Tag::Heading { id, .. }) => {
+                in_heading = true;
+                heading.clear();
+                sections.push(Section {
+                    heading: None,
+                    anchor: id.map(|id| format!("{}{id}", sanitizer.id_prefix)),
+                    text: String::new(),
+                });
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                if let Some(target) = link_target(username, path, &dest_url) {
+                    block_links.push((block.len(), LinkTarget::Path(target)));
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if in_heading {
+                    heading.push_str(&text);
+                }
+
+                // Formulas are searched by their LaTeX
+                let text = math_source(&text, &math);
+                if !in_code_block && !is_code {
+                    for captures in tag_regex().captures_iter(&text) {
+                        let tag = captures[1].trim_matches('/');
+                        if tag.chars().any(|c| !c.is_numeric() && c != '/') {
+                            tags.push(tag.to_lowercase());
+                        }
+                    }
+
+                    for captures in wiki_link_regex().captures_iter(&text) {
+                        let target = captures[2].trim();
+                        if !target.is_empty() {
+                            let offset = block.len() + captures.get(0).map_or(0, |m| m.start());
+                            block_links.push((offset, LinkTarget::Wiki(target.to_string())));
+                        }
+                    }
+                }
+
+                if !in_heading {
+                    let section = sections.last_mut().expect("Sections are never empty");
+                    section.text.push_str(&text);
+                }
+                block.push_str(&text);
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                let section = sections.last_mut().expect("Sections are never empty");
+                if !section.text.ends_with(' ') {
+                    section.text.push(' ');
+                }
+                block.push(' ');
+            }
+            Event::End(
+                tag @ (TagEnd::Heading(_)
+                | TagEnd::Paragraph
+                | TagEnd::Item
+                | TagEnd::TableCell
+                | TagEnd::CodeBlock),
+            ) => {
+                match tag {
+                    TagEnd::Heading(_) => {
+                        in_heading = false;
+                        // Headings read like in the table of contents
+                        let text = wiki_link_text(&strip_math(&heading)).trim().to_string();
+                        let section = sections.last_mut().expect("Sections are never empty");
+                        section.heading = Some(text).filter(|text| !text.is_empty());
+                    }
+                    TagEnd::CodeBlock => in_code_block = false,
+                    _ => {}
+                }
+
+                let section = sections.last_mut().expect("Sections are never empty");
+                if !section.text.is_empty() && !section.text.ends_with(' ') {
+                    section.text.push(' ');
+                }
+
+                for (offset, target) in block_links.drain(..) {
+                    links.push(OutgoingLink {
+                        target,
+                        context: sentence_around(&block, offset),
+                    });
+                }
+                block.clear();
+            }
+            _ => {}
+        }
+    }
+
+    let title = metadata
+        .title
+        .clone()
+        .or_else(|| sections.iter().find_map(|section| section.heading.clone()))
+        .unwrap_or_else(|| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            name.strip_suffix(".md").unwrap_or(name).to_string()
+        });
+
+    tags.sort();
+    tags.dedup();
+
+    // Drop the leading section if the note starts with a heading
+    if sections[0].text.trim().is_empty() {
+        sections.remove(0);
+    }
+
+    IndexedNote {
+        modified,
+        len,
+        title,
+        metadata,
+        tags,
+        sections,
+        links,
+    }
+}
+
+/// Matches inline `#tags`, which have to follow whitespace or start the text.
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"(?:^|\s)#([\w/-]+)").expect("Invalid regex"))
+}
+
+/// Yields a tag and each of its parents, e.g. `a/b/c`, `a/b` and `a`.
+fn tag_and_parents(tag: &str) -> impl Iterator<Item = &str> {
+    once(tag).chain(tag.match_indices('/').map(|(i, _)| &tag[..i]))
+}
+
+/// Resolves the destination of a markdown link to a note path.
+///
+/// Only links to the user's own notes are kept, external links and links to
+/// other users are ignored.
+fn link_target(username: &str, from: &str, dest: &str) -> Option<String> {
+    let dest = dest.split(['#', '?']).next().unwrap_or_default();
+    if dest.is_empty() || dest.contains(':') {
+        return None;
+    }
+
+    let dest = decode_path(dest);
+    let path = match dest.strip_prefix(&format!("/{username}/notes/")) {
+        Some(path) => path.to_string(),
+        None if dest.starts_with('/') => return None,
+        None => match from.rsplit_once('/') {
+            Some((dir, _)) => format!("{dir}/{dest}"),
+            None => dest,
+        },
+    };
+
+    // Resolve `.` and `..`, rejecting paths leaving the notes directory
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let path = segments.join("/");
+    match path.ends_with(".md") {
+        true => Some(path),
+        false if path.is_empty() => Some("index.md".to_string()),
+        false => Some(format!("{path}/index.md")),
+    }
+}
+
+/// Rewrites the links of a note after notes were renamed or moved.
+///
+/// `moved` maps the old paths of every moved note to their new paths and
+/// `source` is the old path of the note being rewritten, which may have moved
+/// itself. Wiki links are resolved with `before` and only use a full path
+/// afterwards if the new name alone would resolve to a different note in `after`.
+///
+/// Returns `None` if no link had to change.
+pub fn rewrite_links(
+    username: &str,
+    source: &str,
+    markdown: &str,
+    moved: &HashMap<String, String>,
+    before: &LinkResolver,
+    after: &LinkResolver,
+) -> Option<String> {
+    let new_source = moved.get(source).map_or(source, String::as_str);
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+    for (event, range) in Parser::new_ext(markdown, Options::all()).into_offset_iter() {
+        let Event::Start(Tag::Link {
+            link_type: LinkType::Inline,
+            dest_url,
+            ..
+        }) = event
+        else {
+            continue;
+        };
+
+        let Some(target) = link_target(username, source, &dest_url) else {
+            continue;
+        };
+        let new_target = moved.get(&target).unwrap_or(&target);
+        let absolute = dest_url.starts_with('/');
+
+        // Absolute links only break if their target moved, relative ones also
+        // break if the note containing them moved
+        if new_target == &target && (absolute || new_source == source) {
+            continue;
+        }
+
+        let (path, fragment) =
+            dest_url.split_at(dest_url.find(['#', '?']).unwrap_or(dest_url.len()));
+        let mut new_path = new_target.as_str();
+        if !path.ends_with(".md") {
+            // Links to folders keep pointing at the folder
+            new_path = new_path
+                .strip_suffix("index.md")
+                .unwrap_or(new_path)
+                .trim_end_matches('/');
+        }
+
+        let new_dest = match absolute {
+            true => format!("/{username}/notes/{}{fragment}", encode_path(new_path)),
+            false => {
+                let dir = new_source.rsplit_once('/').map_or("", |(dir, _)| dir);
+                format!("{}{fragment}", encode_path(&relative_path(dir, new_path)))
+            }
+        };
+
+        if let Some(dest) = link_destination(markdown, range) {
+            if markdown[dest.clone()] != new_dest {
+                edits.push((dest, new_dest));
+            }
+        }
+    }
+
+    let literals = literal_ranges(markdown);
+    for captures in wiki_link_regex().captures_iter(markdown) {
+        let target = captures.get(2).expect("Capture group 2 always exists");
+        if literals
+            .iter()
+            .any(|literal| literal.contains(&target.start()))
+        {
+            continue;
+        }
+
+        let text = target.as_str().trim();
+        let Some(new_path) = before
+            .resolve_note(text)
+            .and_then(|path| moved.get(&format!("{path}.md")))
+        else {
+            continue;
+        };
+
+        let new_path = new_path.strip_suffix(".md").unwrap_or(new_path);
+        let name = new_path.rsplit('/').next().unwrap_or(new_path);
+        let mut new_text = match text.contains('/') || after.resolve_note(name) != Some(new_path) {
+            true => new_path.to_string(),
+            false => name.to_string(),
+        };
+        if text.ends_with(".md") {
+            new_text.push_str(".md");
+        }
+
+        if new_text != text {
+            let start = target.start() + target.as_str().find(text).unwrap_or(0);
+            edits.push((start..start + text.len(), new_text));
+        }
+    }
+
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut output = markdown.to_string();
+    edits.sort_by_key(|(range, _)| range.start);
+    for (range, text) in edits.into_iter().rev() {
+        output.replace_range(range, &text);
+    }
+    Some(output)
+}
+
+/// Finds the destination of an inline link in the markdown it was parsed from.
+fn link_destination(markdown: &str, link: Range<usize>) -> Option<Range<usize>> {
+    let text = &markdown[link.clone()];
+    let open = text.rfind("](")? + 2;
+    let rest = &text[open..];
+    let skipped = rest.len() - rest.trim_start().len();
+    let rest = rest.trim_start();
+
+    let (start, len) = match rest.strip_prefix('<') {
+        Some(inner) => (skipped + 1, inner.find('>')?),
+        None => (skipped, rest.find(|c: char| c.is_whitespace() || c == ')')?),
+    };
+
+    let start = link.start + open + start;
+    Some(start..start + len)
+}
+
+/// Path to `to` from the folder `from`, both relative to `notes/`.
+fn relative_path(from: &str, to: &str) -> String {
+    let from: Vec<&str> = from.split('/').filter(|s| !s.is_empty()).collect();
+    let to: Vec<&str> = to.split('/').filter(|s| !s.is_empty()).collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut segments = vec![".."; from.len() - common];
+    segments.extend(&to[common..]);
+
+    match segments.is_empty() {
+        true => ".".to_string(),
+        false => segments.join("/"),
+    }
+}
+
+/// Extracts the sentence of `text` containing the byte at `offset`.
+fn sentence_around(text: &str, offset: usize) -> String {
+    let ends_sentence = |i: usize, c: char| {
+        matches!(c, '.' | '!' | '?')
+            && text[i + c.len_utf8()..]
+                .chars()
+                .next()
+                .map_or(true, char::is_whitespace)
+    };
+
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| ends_sentence(i, c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = text[offset..]
+        .char_indices()
+        .find(|&(i, c)| ends_sentence(offset + i, c))
+        .map_or(text.len(), |(i, c)| offset + i + c.len_utf8());
+
+    let sentence = text[start..end].trim();
+    match sentence.char_indices().nth(CONTEXT_LENGTH) {
+        Some((cut, _)) => format!("{}…", &sentence[..cut]),
+        None => sentence.to_string(),
+    }
+}
+
+/// Cuts a window around the first match out of `text` and marks every match in it.
+fn build_snippet(text: &str, matchers: &[Regex]) -> Vec<SnippetPart> {
+    let first = matchers
+        .iter()
+        .filter_map(|matcher| matcher.find(text).map(|m| m.start()))
+        .min()
+        .unwrap_or(0);
+
+    let start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_RADIUS)
+        .map_or(0, |(i, _)| i);
+    let end = text[first..]
+        .char_indices()
+        .nth(SNIPPET_RADIUS * 2)
+        .map_or(text.len(), |(i, _)| first + i);
+    let window = &text[start..end];
+
+    // Collect non-overlapping match ranges inside the window
+    let mut ranges: Vec<(usize, usize)> = matchers
+        .iter()
+        .flat_map(|matcher| matcher.find_iter(window).map(|m| (m.start(), m.end())))
+        .collect();
+    ranges.sort();
+
+    let part = |text: &str, highlight| SnippetPart {
+        text: text.to_string(),
+        highlight,
+    };
+
+    let mut parts = Vec::new();
+    let mut cursor = 0;
+
+    if start > 0 {
+        parts.push(part("…", false));
+    }
+
+    for (from, to) in ranges {
+        if from < cursor {
+            continue;
+        }
+        if from > cursor {
+            parts.push(part(&window[cursor..from], false));
+        }
+        parts.push(part(&window[from..to], true));
+        cursor = to;
+    }
+
+    if cursor < window.len() {
+        parts.push(part(&window[cursor..], false));
+    }
+
+    if end < text.len() {
+        parts.push(part("…", false));
+    }
+
+    parts
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::api::{convert_to_html, TagCount};
+    use crate::index::{
+        build_snippet, collect_files, index_note, link_target, relative_path, rewrite_links,
+        sentence_around, LinkTarget, NotesIndex, OutgoingLink, Section, UserIndex,
+    };
+    use crate::markdown::LinkResolver;
+    use crate::paths::SymlinkPolicy;
+    use crate::sanitizer::SanitizerSettings;
+    use regex::RegexBuilder;
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn note_sections() {
+        let note = index_note(
+            "bob",
+            "folder/runbook.md",
+            "Intro text\n\n# Title {#title}\n\nFirst *section*\n\n## Sub\n\n- item `code`\n",
+            None,
+            0,
+            &SanitizerSettings::default(),
+        );
+
+        assert_eq!(note.title, "Title");
+        assert_eq!(
+            note.sections,
+            vec![
+                Section {
+                    heading: None,
+                    anchor: None,
+                    text: "Intro text ".into()
+                },
+                Section {
+                    heading: Some("Title".into()),
+                    anchor: Some("ln-title".into()),
+                    text: "First section ".into()
+                },
+                Section {
+                    heading: Some("Sub".into()),
+                    anchor: Some("ln-sub".into()),
+                    text: "item code ".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn math_sections() {
+        let markdown = "$$\n# Not a heading\n$$\n\n# Area $r^2$\n\nIt is $\\pi r^2$.\n\n# Next\n";
+        let sanitizer = SanitizerSettings::default();
+        let note = index_note("bob", "circle.md", markdown, None, 0, &sanitizer);
+        let (_, toc) = convert_to_html(markdown, &LinkResolver::default(), &sanitizer, 0);
+
+        let headings: Vec<_> = note.sections[1..]
+            .iter()
+            .map(|section| (section.heading.clone().unwrap(), section.anchor.clone().unwrap()))
+            .collect();
+        let rendered: Vec<_> = toc
+            .into_iter()
+            .map(|heading| (heading.text, heading.id))
+            .collect();
+        assert_eq!(headings, rendered);
+        assert_eq!(headings[1], ("Next".to_string(), "ln-next".to_string()));
+
+        // Formulas are indexed by their LaTeX
+        assert!(note.sections[0].text.contains("# Not a heading"));
+        assert_eq!(note.sections[1].text, "It is \\pi r^2. ");
+    }
+
+    #[test]
+    fn title_falls_back_to_file_name() {
+        let note = index_note(
+            "bob",
+            "folder/runbook.md",
+            "no headings here",
+            None,
+            0,
+            &SanitizerSettings::default(),
+        );
+        assert_eq!(note.title, "runbook");
+    }
+
+    #[test]
+    fn frontmatter_title() {
+        let note = index_note(
+            "bob",
+            "runbook.md",
+            "---\ntitle: On-call runbook\naliases: [Pager]\n---\n# Steps\nRestart it.",
+            None,
+            0,
+            &SanitizerSettings::default(),
+        );
+        assert_eq!(note.title, "On-call runbook");
+        assert_eq!(note.metadata.aliases, vec!["Pager"]);
+        assert!(!note.sections.iter().any(|section| section.text.contains("Pager")));
+    }
+
+    #[test]
+    fn note_links() {
+        let note = index_note(
+            "bob",
+            "folder/runbook.md",
+            "Intro. See [the plan](../plan.md#goals) for details. Also [[Alpha]]!\n\n\
+             `[[Not a link]]` and [elsewhere](https://example.com).",
+            None,
+            0,
+            &SanitizerSettings::default(),
+        );
+
+        assert_eq!(
+            note.links,
+            vec![
+                OutgoingLink {
+                    target: LinkTarget::Path("plan.md".into()),
+                    context: "See the plan for details.".into()
+                },
+                OutgoingLink {
+                    target: LinkTarget::Wiki("Alpha".into()),
+                    context: "Also [[Alpha]]!".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_tags() {
+        let note = index_note(
+            "bob",
+            "note.md",
+            "---\ntags: [Work]\n---\n# Heading #idea\n\n#Project/Alpha/ and #todo, not#this \
+             or #123 or [[note#heading]].\n\n`#code` and\n\n```\n#comment\n```\n",
+            None,
+            0,
+            &SanitizerSettings::default(),
+        );
+        assert_eq!(note.tags, vec!["idea", "project/alpha", "todo", "work"]);
+    }
+
+    #[test]
+    fn tag_rollup() {
+        let mut index = UserIndex::default();
+        for (path, markdown, secs) in [
+            ("alpha.md", "#project/alpha #status/open", 300),
+            ("beta.md", "#project/beta #project", 200),
+            ("notes/misc.md", "#status/open", 100),
+        ] {
+            let modified = Some(UNIX_EPOCH + Duration::from_secs(secs));
+            let note = index_note(
+                "bob",
+                path,
+                markdown,
+                modified,
+                0,
+                &SanitizerSettings::default(),
+            );
+            index.notes.insert(path.to_string(), note);
+        }
+
+        let count = |tag: &str, count| TagCount {
+            tag: tag.to_string(),
+            count,
+        };
+        assert_eq!(
+            index.tag_counts(),
+            vec![
+                count("project", 2),
+                count("project/alpha", 1),
+                count("project/beta", 1),
+                count("status", 2),
+                count("status/open", 2),
+            ]
+        );
+
+        let paths = |tag| -> Vec<String> {
+            index.tagged_notes(tag).into_iter().map(|note| note.path).collect()
+        };
+        assert_eq!(paths("project"), vec!["alpha.md", "beta.md"]);
+        assert_eq!(paths("#Status/Open"), vec!["alpha.md", "notes/misc.md"]);
+        assert_eq!(index.tagged_notes("project")[0].modified, Some(300));
+        assert!(paths("proj").is_empty());
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path("", "notes/a.md"), "notes/a.md");
+        assert_eq!(relative_path("a/b", "a/c/d.md"), "../c/d.md");
+        assert_eq!(relative_path("a/b", "a/b/c.md"), "c.md");
+        assert_eq!(relative_path("a/b", "a/b"), ".");
+        assert_eq!(relative_path("a", "index.md"), "../index.md");
+    }
+
+    #[test]
+    fn renamed_note_links() {
+        let notes =
+            |paths: &[&str]| -> Vec<String> { paths.iter().map(|p| p.to_string()).collect() };
+        let before = LinkResolver::new("bob", notes(&["index.md", "projects/alpha.md"]), []);
+        let after = LinkResolver::new("bob", notes(&["index.md", "archive/alpha-plan.md"]), []);
+        let moved = HashMap::from([(
+            "projects/alpha.md".to_string(),
+            "archive/alpha-plan.md".to_string(),
+        )]);
+
+        let markdown = "[plan](projects/alpha.md#goals), [abs](/bob/notes/projects/alpha.md), \
+                        [[alpha]], ![[ projects/alpha#Goals|the plan]], `[[alpha]]`, \
+                        [other](other.md) and [folder](projects)";
+        assert_eq!(
+            rewrite_links("bob", "index.md", markdown, &moved, &before, &after).as_deref(),
+            Some(
+                "[plan](archive/alpha-plan.md#goals), [abs](/bob/notes/archive/alpha-plan.md), \
+                 [[alpha-plan]], ![[ archive/alpha-plan#Goals|the plan]], `[[alpha]]`, \
+                 [other](other.md) and [folder](projects)"
+            )
+        );
+
+        // Names which would resolve to another note are replaced by the full path
+        let after = LinkResolver::new(
+            "bob",
+            notes(&["alpha-plan.md", "archive/alpha-plan.md"]),
+            [],
+        );
+        assert_eq!(
+            rewrite_links("bob", "index.md", "[[Alpha]]", &moved, &before, &after).as_deref(),
+            Some("[[archive/alpha-plan]]")
+        );
+
+        assert_eq!(
+            rewrite_links("bob", "index.md", "[[index]]", &moved, &before, &after),
+            None
+        );
+    }
+
+    #[test]
+    fn moved_folder_links() {
+        let notes = [
+            "index.md".to_string(),
+            "projects/a.md".into(),
+            "projects/b.md".into(),
+        ];
+        let before = LinkResolver::new("bob", notes, []);
+        let after = LinkResolver::new(
+            "bob",
+            [
+                "index.md".to_string(),
+                "work/projects/a.md".into(),
+                "work/projects/b.md".into(),
+            ],
+            [],
+        );
+        let moved = HashMap::from([
+            (
+                "projects/a.md".to_string(),
+                "work/projects/a.md".to_string(),
+            ),
+            (
+                "projects/b.md".to_string(),
+                "work/projects/b.md".to_string(),
+            ),
+        ]);
+
+        // Relative links between moved notes stay the same
+        assert_eq!(
+            rewrite_links(
+                "bob",
+                "projects/a.md",
+                "[up](../index.md) [sibling](b.md) [[b]]",
+                &moved,
+                &before,
+                &after
+            )
+            .as_deref(),
+            Some("[up](../../index.md) [sibling](b.md) [[b]]")
+        );
+        assert_eq!(
+            rewrite_links(
+                "bob",
+                "index.md",
+                "[a](<projects/a.md>)",
+                &moved,
+                &before,
+                &after
+            )
+            .as_deref(),
+            Some("[a](<work/projects/a.md>)")
+        );
+    }
+
+    #[test]
+    fn link_targets() {
+        assert_eq!(link_target("bob", "a/b.md", "c.md"), Some("a/c.md".into()));
+        assert_eq!(link_target("bob", "a/b.md", "./c%20d.md"), Some("a/c d.md".into()));
+        assert_eq!(link_target("bob", "a/b.md", "/bob/notes/x/y.md"), Some("x/y.md".into()));
+        assert_eq!(link_target("bob", "a/b.md", "/bob/notes/x"), Some("x/index.md".into()));
+        assert_eq!(link_target("bob", "a/b.md", "../../escape.md"), None);
+        assert_eq!(link_target("bob", "a/b.md", "/alice/notes/x.md"), None);
+        assert_eq!(link_target("bob", "a/b.md", "mailto:bob@example.com"), None);
+    }
+
+    #[test]
+    fn link_context() {
+        let text = "First sentence. The link is here! Last one";
+        assert_eq!(sentence_around(text, 20), "The link is here!");
+        assert_eq!(sentence_around(text, 0), "First sentence.");
+        assert_eq!(sentence_around(text, 38), "Last one");
+    }
+
+    #[test]
+    fn snippet_highlighting() {
+        let matcher = RegexBuilder::new("rust")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let parts = build_snippet("I like Rust and rust likes me", &[matcher]);
+        let marked: Vec<_> = parts
+            .iter()
+            .filter(|part| part.highlight)
+            .map(|part| part.text.as_str())
+            .collect();
+
+        assert_eq!(marked, vec!["Rust", "rust"]);
+        assert_eq!(
+            parts.iter().map(|part| part.text.as_str()).collect::<String>(),
+            "I like Rust and rust likes me"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_files() {
+        use std::fs::{create_dir_all, write};
+        use std::os::unix::fs::symlink;
+
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("notes");
+        create_dir_all(root.join("projects")).unwrap();
+        create_dir_all(temp.path().join("secrets")).unwrap();
+        write(root.join("projects/plan.md"), "# Plan").unwrap();
+        write(temp.path().join("secrets/passwords.md"), "hunter2").unwrap();
+        symlink("..", root.join("projects/up")).unwrap();
+        symlink("plan.md", root.join("projects/latest.md")).unwrap();
+        symlink(temp.path().join("secrets"), root.join("secrets")).unwrap();
+
+        let files = |policy| {
+            let mut files = Vec::new();
+            collect_files(&root, policy, &mut |path, _| files.push(path));
+            files.sort();
+            files
+        };
+
+        // Links back up the tree are listed once instead of recursing forever
+        assert_eq!(
+            files(SymlinkPolicy::WithinRoot),
+            vec!["projects/latest.md", "projects/plan.md"]
+        );
+        assert_eq!(files(SymlinkPolicy::Deny), vec!["projects/plan.md"]);
+        assert_eq!(
+            files(SymlinkPolicy::Allow),
+            vec!["projects/latest.md", "projects/plan.md", "secrets/passwords.md"]
+        );
+    }
+
+    #[test]
+    fn link_generations() {
+        use std::fs::{create_dir_all, write};
+
+        let temp = tempfile::tempdir().unwrap();
+        let notes = temp.path().join("notes");
+        let resources = temp.path().join("resources");
+        create_dir_all(&notes).unwrap();
+        create_dir_all(&resources).unwrap();
+        write(notes.join("index.md"), "# Home").unwrap();
+
+        let index = NotesIndex::default();
+        let generation = || index.generation("bob", &notes, &resources);
+        let first = generation();
+        assert_eq!(generation(), first);
+
+        // Editing a note doesn't change what links resolve to
+        write(notes.join("index.md"), "# Home\n\nMore text").unwrap();
+        assert_eq!(generation(), first);
+
+        write(notes.join("plan.md"), "# Plan").unwrap();
+        let second = generation();
+        assert_ne!(second, first);
+
+        write(notes.join("plan.md"), "---\naliases: Roadmap\n---\n# Plan").unwrap();
+        let third = generation();
+        assert_ne!(third, second);
+
+        // Resources are listed again once Lazy Notes reports changing them
+        write(resources.join("diagram.png"), "").unwrap();
+        index.invalidate("bob");
+        assert_ne!(generation(), third);
+    }
+}
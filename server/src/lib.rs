@@ -2,8 +2,17 @@ use cfg_if::cfg_if;
 pub mod api;
 pub mod app;
 pub mod auth;
+pub mod cache;
+pub mod history;
+pub mod index;
+pub mod markdown;
+pub mod paths;
+pub mod sanitizer;
 pub mod settings;
 pub mod state;
+pub mod trash;
+pub mod uploads;
+pub mod watcher;
 // pub mod error_template;
 // pub mod fileserv;
 
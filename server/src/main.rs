@@ -3,27 +3,38 @@ use cfg_if::cfg_if;
 cfg_if!( if #[cfg(feature = "ssr")] {
     use axum::{
         body::Body,
-        http::{Request, StatusCode},
-        routing::get,
-        response::{IntoResponse, Response},
-        extract::{Path, State}, Router
+        http::{header, HeaderValue, Request, StatusCode},
+        routing::{get, post},
+        response::{sse::{self, KeepAlive, Sse}, IntoResponse, Response},
+        extract::{DefaultBodyLimit, Multipart, Path, State}, Json, Router
     };
     use axum_session::{SessionConfig, SessionLayer, SessionStore};
     use axum_session_auth::{AuthConfig, AuthSession, AuthSessionLayer, SessionSurrealPool};
-    use leptos::logging::log;
+    use leptos::logging::{self, log};
     use leptos::*;
     use leptos_axum::{generate_route_list, handle_server_fns_with_context, LeptosRoutes};
+    use futures::StreamExt;
     use log::Level::Error;
+    use std::convert::Infallible;
     use std::env;
     use surrealdb::{engine::remote::ws::{Client, Ws}, opt::auth::Namespace, Surreal};
     use chrono::Duration;
     use tower::util::ServiceExt;
-    use tower_http::services::ServeDir;
+    use tokio::io::AsyncWriteExt;
+    use tokio_stream::wrappers::BroadcastStream;
+    use tower_http::services::{ServeDir, ServeFile};
 
+    use lazy_notes::api::{is_hidden, notes_root, resources_path, UploadedFile};
     use lazy_notes::app::*;
     use lazy_notes::auth::User;
+    use lazy_notes::cache::RenderCache;
+    use lazy_notes::index::NotesIndex;
+    use lazy_notes::paths;
     use lazy_notes::settings;
     use lazy_notes::state::AppState;
+    use lazy_notes::trash;
+    use lazy_notes::uploads;
+    use lazy_notes::watcher::NoteWatcher;
 });
 
 #[cfg(feature = "ssr")]
@@ -38,6 +49,7 @@ async fn main() {
         .expect("Failed to read configuration file");
     let ln_settings = ln_config.settings;
     let db_settings = ln_config.database;
+    let sanitizer = ln_config.sanitizer;
 
     // Setup SurrealDB
     let db = Surreal::new::<Ws>(db_settings.db_host) //.expect("No DB host specified"))
@@ -58,10 +70,51 @@ async fn main() {
     let auth_config = AuthConfig::<String>::default()
         .with_max_age(Duration::try_weeks(2).expect("Overflow on max session age"));
     let session_store =
-        SessionStore::<SessionSurrealPool<Client>>::new(Some(pool.clone().into()), session_config)
+        SessionStore::<SessionSurrealPool<Client>>::new(Some(pool.clone()), session_config)
             .await
             .unwrap();
 
+    // Purge expired trash every hour
+    if ln_settings.trash_retention_days > 0 {
+        let data_dir = ln_settings.data_dir.clone();
+        let retention_days = ln_settings.trash_retention_days;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let data_dir = data_dir.clone();
+                let _ = tokio::task::spawn_blocking(move || {
+                    trash::purge_expired(&data_dir, retention_days)
+                })
+                .await;
+            }
+        });
+    }
+
+    let watcher = NoteWatcher::new(&ln_settings.data_dir);
+
+    // Report how well the render cache works every hour
+    let render_cache = RenderCache::new(ln_settings.render_cache_capacity);
+    if ln_settings.render_cache_capacity > 0 {
+        let render_cache = render_cache.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let stats = render_cache.stats();
+                log!(
+                    "Render cache: {} hits, {} misses, {} notes cached",
+                    stats.hits,
+                    stats.misses,
+                    stats.len
+                );
+            }
+        });
+    }
+
     // Get env values for leptos
     let conf = get_configuration(None).await.unwrap();
     let leptos_options = conf.leptos_options;
@@ -69,11 +122,23 @@ async fn main() {
     let root = leptos_options.site_root.as_str();
     let routes = generate_route_list(App);
 
+    // Each file is checked on its own, the request only has to fit all of them. Leave room
+    // for the multipart boundaries and the other form fields.
+    let upload_limit = usize::try_from(ln_settings.max_upload_size)
+        .unwrap_or(usize::MAX)
+        .saturating_mul(ln_settings.max_upload_files)
+        .saturating_add(64 * 1024);
+
     let app = Router::new()
         .nest_service("/pkg", ServeDir::new(format!("{root}/pkg")))
         .nest_service("/scripts", ServeDir::new(format!("{root}/scripts")))
         .nest_service("/icons", ServeDir::new(format!("{root}/icons")))
         .route("/:user/resources/*file", get(note_resource_handler))
+        .route("/:user/events", get(note_events_handler))
+        .route(
+            "/:user/upload",
+            post(upload_handler).layer(DefaultBodyLimit::max(upload_limit)),
+        )
         .route(
             "/api/*fn_name",
             get(server_fn_handler).post(server_fn_handler),
@@ -89,9 +154,12 @@ async fn main() {
         .layer(SessionLayer::new(session_store))
         .with_state(AppState {
             leptos_options,
+            index: NotesIndex::new(sanitizer, ln_settings.symlink_policy, watcher.clone()),
+            watcher,
             settings: ln_settings,
             pool: db,
             routes,
+            render_cache,
         });
     // .nest("/api", api_routes());
 
@@ -116,6 +184,8 @@ async fn leptos_routes_handler(
             provide_context(auth_session.clone());
             provide_context(app_state.pool.clone());
             provide_context(app_state.settings.clone());
+            provide_context(app_state.index.clone());
+            provide_context(app_state.render_cache.clone());
         },
         App,
     );
@@ -135,6 +205,8 @@ async fn server_fn_handler(
             provide_context(auth_session.clone());
             provide_context(app_state.pool.clone());
             provide_context(app_state.settings.clone());
+            provide_context(app_state.index.clone());
+            provide_context(app_state.render_cache.clone());
         },
         request,
     )
@@ -144,12 +216,10 @@ async fn server_fn_handler(
 #[cfg(feature = "ssr")]
 async fn note_resource_handler(
     auth: AuthSession<User, String, SessionSurrealPool<Client>, Surreal<Client>>,
-    Path((username, _file)): Path<(String, String)>,
+    Path((username, file)): Path<(String, String)>,
     State(state): State<AppState>,
     req: Request<Body>,
 ) -> Response {
-    let root = state.settings.data_dir;
-
     if !auth.is_authenticated()
         || auth.current_user.expect("User is authenticated").username != username
     {
@@ -160,8 +230,42 @@ async fn note_resource_handler(
             .into_response();
     }
 
-    match ServeDir::new(root).oneshot(req).await {
-        Ok(res) => res.into_response(),
+    // Files kept next to the notes are served too, for folder listings to link to
+    let mut roots = vec![resources_path(&state.settings, &username)];
+    if !is_hidden(&file) {
+        roots.push(notes_root(&state.settings, &username));
+    }
+
+    let mut found = None;
+    for root in roots {
+        match paths::resolve(&root, &file, state.settings.symlink_policy) {
+            Ok(path) if path.is_file() => {
+                found = Some(path);
+                break;
+            }
+            Ok(_) => {}
+            Err(_) => return (StatusCode::FORBIDDEN, "Invalid resource path").into_response(),
+        }
+    }
+    let Some(path) = found else {
+        return (StatusCode::NOT_FOUND, "Resource not found").into_response();
+    };
+
+    match ServeFile::new(path).oneshot(req).await {
+        Ok(res) => {
+            // Uploads must never run as pages of this site
+            let mut res = res.into_response();
+            let headers = res.headers_mut();
+            let inline = headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|mime| mime.to_str().ok())
+                .is_some_and(uploads::is_inline_type);
+            headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            if !inline {
+                headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+            }
+            res
+        }
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Something went wrong: {err}"),
@@ -169,3 +273,197 @@ async fn note_resource_handler(
             .into_response(),
     }
 }
+
+/// Stores files sent as multipart `file` fields in the user's resources directory.
+///
+/// An optional `folder` field sent before the files selects a folder below
+/// `resources/`. Files are never overwritten: name collisions get a numbered
+/// suffix instead.
+#[cfg(feature = "ssr")]
+async fn upload_handler(
+    auth: AuthSession<User, String, SessionSurrealPool<Client>, Surreal<Client>>,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Response {
+    if !auth.is_authenticated()
+        || auth.current_user.expect("User is authenticated").username != username
+    {
+        return (StatusCode::UNAUTHORIZED, "Uploading requires permission").into_response();
+    }
+
+    let settings = state.settings;
+    let resources_dir = resources_path(&settings, &username);
+    let mut folder = resources_dir.clone();
+    let mut prefix = String::new();
+    let mut staged = uploads::StagedUpload::default();
+    let mut prefixes = Vec::new();
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return (err.status(), err.body_text()).into_response(),
+        };
+
+        let field_name = field.name().unwrap_or_default().to_string();
+        match field_name.as_str() {
+            "folder" => {
+                let name = match field.text().await {
+                    Ok(name) => name.trim().trim_matches('/').to_string(),
+                    Err(err) => return (err.status(), err.body_text()).into_response(),
+                };
+
+                let policy = settings.symlink_policy;
+                folder = match uploads::resolve_folder(&resources_dir, &name, policy) {
+                    Some(folder) => folder,
+                    None => {
+                        return (StatusCode::BAD_REQUEST, format!("Invalid folder: {name}"))
+                            .into_response()
+                    }
+                };
+                prefix = match name.is_empty() {
+                    true => String::new(),
+                    false => format!("{name}/"),
+                };
+            }
+            "file" => {
+                let Some(name) = field.file_name().and_then(uploads::sanitize_file_name) else {
+                    return (StatusCode::BAD_REQUEST, "Uploaded file has no name").into_response();
+                };
+
+                if prefixes.len() >= settings.max_upload_files {
+                    return (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "At most {} files can be uploaded at once",
+                            settings.max_upload_files
+                        ),
+                    )
+                        .into_response();
+                }
+
+                let mut file = match staged.stage(&folder, &name) {
+                    Ok(file) => tokio::fs::File::from_std(file),
+                    Err(err) => {
+                        logging::error!("Failed to stage upload {name}: {err}");
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload")
+                            .into_response();
+                    }
+                };
+
+                // The type is told from the data, as the one sent by the browser can't be trusted
+                let mut head = Vec::new();
+                let mut size = 0;
+                loop {
+                    let chunk = match field.chunk().await {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => break,
+                        Err(err) => return (err.status(), err.body_text()).into_response(),
+                    };
+
+                    size += chunk.len() as u64;
+                    if size > settings.max_upload_size {
+                        return (
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            format!("{name} is larger than the upload limit"),
+                        )
+                            .into_response();
+                    }
+
+                    let missing = uploads::SNIFF_LENGTH.saturating_sub(head.len());
+                    head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+
+                    if let Err(err) = file.write_all(&chunk).await {
+                        logging::error!("Failed to save upload {name}: {err}");
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload")
+                            .into_response();
+                    }
+                }
+
+                let mime = uploads::sniff_type(&head);
+                if !uploads::is_allowed_type(mime, &settings.allowed_upload_types) {
+                    return (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        format!("Files of type {mime} can not be uploaded"),
+                    )
+                        .into_response();
+                }
+                // Files are served with the type of their extension
+                if !uploads::matches_extension(&name, mime) {
+                    return (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        format!("{name} holds {mime}, which doesn't fit its extension"),
+                    )
+                        .into_response();
+                }
+
+                if let Err(err) = file.sync_all().await {
+                    logging::error!("Failed to save upload {name}: {err}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload")
+                        .into_response();
+                }
+                prefixes.push(prefix.clone());
+            }
+            _ => {}
+        }
+    }
+
+    // Nothing is saved unless every file was received
+    let committed = tokio::task::spawn_blocking(move || staged.commit()).await;
+    let saved = match committed.map_err(std::io::Error::other).and_then(|saved| saved) {
+        Ok(saved) => saved,
+        Err(err) => {
+            logging::error!("Failed to save upload: {err}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload").into_response();
+        }
+    };
+
+    let uploaded: Vec<UploadedFile> = prefixes
+        .into_iter()
+        .zip(saved)
+        .map(|(prefix, saved)| {
+            let path = format!("{prefix}{saved}");
+            UploadedFile {
+                markdown: uploads::resource_markdown(&path),
+                path,
+            }
+        })
+        .collect();
+
+    state.index.invalidate(&username);
+    Json(uploaded).into_response()
+}
+
+/// Streams the paths of the user's notes as they change on disk, as server-sent events.
+///
+/// A `*` means changes were missed and anything open should be reloaded.
+#[cfg(feature = "ssr")]
+async fn note_events_handler(
+    auth: AuthSession<User, String, SessionSurrealPool<Client>, Surreal<Client>>,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    if !auth.is_authenticated()
+        || auth.current_user.expect("User is authenticated").username != username
+    {
+        return (StatusCode::UNAUTHORIZED, "Events require permission to view").into_response();
+    }
+
+    let changes = match state.watcher.subscribe(&username) {
+        Ok(changes) => changes,
+        Err(err) => {
+            logging::error!("Failed to watch notes of {username}: {err}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to watch notes").into_response();
+        }
+    };
+
+    let events = BroadcastStream::new(changes).map(|change| {
+        Ok::<_, Infallible>(match change {
+            Ok(path) => sse::Event::default().event("change").data(path),
+            Err(_) => sse::Event::default().event("change").data("*"),
+        })
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
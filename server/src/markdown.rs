@@ -0,0 +1,1613 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::{encode_path, NoteMetadata, TocHeading};
+use ammonia::clean_text;
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use leptos::logging;
+use pulldown_cmark::{
+    utils::TextMergeStream, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd,
+};
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::iter::{once, Peekable};
+use std::mem::take;
+use std::ops::Range;
+use std::sync::OnceLock;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Extensions of resources embedded as images, videos and audio players.
+pub(crate) const IMAGE_EXTENSIONS: [&str; 8] =
+    ["png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "bmp"];
+pub(crate) const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "webm", "ogv", "mov"];
+pub(crate) const AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "ogg", "wav", "flac", "m4a"];
+
+/// Prefix of every class added by the syntax highlighter.
+pub const HIGHLIGHT_CLASS_PREFIX: &str = "hl-";
+
+/// Prefix the sanitizer adds to ids unless configured otherwise.
+pub const DEFAULT_ID_PREFIX: &str = "ln-";
+
+/// Callout types, each with the other names it can be written as.
+const CALLOUT_KINDS: [(&str, &[&str]); 14] = [
+    ("note", &[]),
+    ("abstract", &["summary", "tldr"]),
+    ("info", &[]),
+    ("todo", &[]),
+    ("tip", &["hint"]),
+    ("important", &[]),
+    ("success", &["check", "done"]),
+    ("question", &["help", "faq"]),
+    ("warning", &["caution", "attention"]),
+    ("failure", &["fail", "missing"]),
+    ("danger", &["error"]),
+    ("bug", &[]),
+    ("example", &[]),
+    ("quote", &["cite"]),
+];
+
+/// Info string attributes which turn on line numbers.
+const LINE_NUMBER_ATTRIBUTES: [&str; 3] = ["linenos", "numbered", "showLineNumbers"];
+
+/// Turns heading text into an id, e.g. `Hello, World!` into `hello-world`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_matches('-').to_string()
+}
+
+/// Splits `---` YAML or `+++` TOML frontmatter off the start of a note.
+///
+/// Frontmatter which fails to parse is still removed but yields no metadata.
+pub fn split_frontmatter(md: &str) -> (NoteMetadata, &str) {
+    let md = md.strip_prefix('\u{feff}').unwrap_or(md);
+    let mut lines = md.split_inclusive('\n');
+
+    let Some(delimiter) = lines.next().map(str::trim_end).filter(|l| ["---", "+++"].contains(l))
+    else {
+        return (NoteMetadata::default(), md);
+    };
+
+    let start = delimiter.len() + md[delimiter.len()..].find('\n').map_or(0, |i| i + 1);
+    let mut end = start;
+    for line in lines {
+        let closing = match delimiter {
+            "---" => ["---", "..."].contains(&line.trim_end()),
+            _ => line.trim_end() == "+++",
+        };
+
+        if closing {
+            let frontmatter = &md[start..end];
+            let metadata = match delimiter {
+                _ if frontmatter.trim().is_empty() => Ok(NoteMetadata::default()),
+                "---" => serde_yaml::from_str(frontmatter).map_err(|e| e.to_string()),
+                _ => toml::from_str(frontmatter).map_err(|e| e.to_string()),
+            };
+
+            let metadata = metadata.unwrap_or_else(|err| {
+                logging::warn!("Failed to parse frontmatter: {err}");
+                NoteMetadata::default()
+            });
+            return (metadata, &md[end + line.len()..]);
+        }
+        end += line.len();
+    }
+
+    // An unclosed delimiter is just a horizontal rule
+    (NoteMetadata::default(), md)
+}
+
+/// Resolves wiki link targets to the notes and resources of a user.
+///
+/// Like Obsidian, a target without a folder matches a file with that name
+/// anywhere in the vault. Ties are broken by picking the least nested file.
+#[derive(Clone, Debug, Default)]
+pub struct LinkResolver {
+    username: String,
+    /// Note paths without `.md`, keyed by their lowercase file name
+    notes: HashMap<String, Vec<String>>,
+    /// Resource paths keyed by their lowercase file name
+    resources: HashMap<String, Vec<String>>,
+    /// Note paths without `.md`, keyed by their lowercase aliases
+    aliases: HashMap<String, Vec<String>>,
+    /// Prefix of heading ids, [`DEFAULT_ID_PREFIX`] if not set
+    id_prefix: Option<String>,
+}
+
+impl LinkResolver {
+    /// Creates a resolver from paths relative to the notes and resources directories.
+    pub fn new(
+        username: &str,
+        notes: impl IntoIterator<Item = String>,
+        resources: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let notes = notes
+            .into_iter()
+            .map(|path| path.strip_suffix(".md").map(String::from).unwrap_or(path));
+
+        Self {
+            username: username.to_string(),
+            notes: group_by_name(notes),
+            resources: group_by_name(resources),
+            aliases: HashMap::new(),
+            id_prefix: None,
+        }
+    }
+
+    /// Links to headings with the id prefix the sanitizer is configured with.
+    pub fn with_id_prefix(mut self, prefix: &str) -> Self {
+        self.id_prefix = Some(prefix.to_string());
+        self
+    }
+
+    fn id_prefix(&self) -> &str {
+        self.id_prefix.as_deref().unwrap_or(DEFAULT_ID_PREFIX)
+    }
+
+    /// Lets notes be linked to by the aliases from their frontmatter.
+    ///
+    /// Takes pairs of note paths and their aliases.
+    pub fn with_aliases<'a>(
+        mut self,
+        aliases: impl IntoIterator<Item = (&'a str, &'a [String])>,
+    ) -> Self {
+        for (path, names) in aliases {
+            let path = path.strip_suffix(".md").unwrap_or(path);
+            for name in names {
+                self.aliases.entry(name.to_lowercase()).or_default().push(path.to_string());
+            }
+        }
+
+        for paths in self.aliases.values_mut() {
+            paths.sort_by(|a, b| {
+                a.matches('/')
+                    .count()
+                    .cmp(&b.matches('/').count())
+                    .then_with(|| a.cmp(b))
+            });
+            paths.dedup();
+        }
+
+        self
+    }
+
+    /// Hash of everything links can resolve to, which changes whenever a link
+    /// may render differently.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.username.hash(&mut hasher);
+        self.id_prefix().hash(&mut hasher);
+
+        // Paths are kept sorted, only the names need ordering
+        for paths in [&self.notes, &self.resources, &self.aliases] {
+            let mut paths: Vec<_> = paths.iter().collect();
+            paths.sort_unstable();
+            paths.hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    /// Finds the path of the note a target refers to.
+    ///
+    /// Aliases are only used if no note has the target as its name.
+    pub fn resolve_note(&self, target: &str) -> Option<&str> {
+        let target = target.strip_suffix(".md").unwrap_or(target);
+        lookup(&self.notes, target).or_else(|| {
+            self.aliases
+                .get(&target.trim().to_lowercase())
+                .and_then(|paths| paths.first())
+                .map(String::as_str)
+        })
+    }
+
+    /// Finds the path of the resource a target refers to.
+    pub fn resolve_resource(&self, target: &str) -> Option<&str> {
+        lookup(&self.resources, target)
+    }
+
+    /// URL of a note, optionally pointing at one of its headings.
+    pub fn note_url(&self, path: &str, heading: Option<&str>) -> String {
+        let mut url = format!("/{}/notes/{}", self.username, encode_path(path));
+        if let Some(heading) = heading {
+            url.push_str(&format!("#{}{}", self.id_prefix(), slugify(heading)));
+        }
+        url
+    }
+
+    /// URL of a file in the resources directory.
+    pub fn resource_url(&self, path: &str) -> String {
+        format!("/{}/resources/{}", self.username, encode_path(path))
+    }
+
+    /// Points a `/resources` url at the resources of the user.
+    fn user_resource_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix("/resources")
+            .map(|rest| format!("/{}/resources{rest}", self.username))
+    }
+
+    /// Renders a single `[[link]]` or `![[embed]]` to HTML.
+    fn render(&self, embed: bool, target: &str, heading: Option<&str>, alias: Option<&str>) -> String {
+        let target = target.trim();
+        let heading = heading.map(str::trim).filter(|heading| !heading.is_empty());
+        let alias = alias.map(str::trim).filter(|alias| !alias.is_empty());
+
+        if embed {
+            if let Some(path) = self.resolve_resource(target) {
+                return self.render_embed(path, alias);
+            }
+        }
+
+        let label = wiki_link_label(target, heading, alias);
+        let class = if embed { "wikilink embed" } else { "wikilink" };
+
+        // Links to a heading of the current note
+        if target.is_empty() {
+            let href = format!("#{}{}", self.id_prefix(), slugify(heading.unwrap_or_default()));
+            return format!("<a class=\"{class}\" href=\"{href}\">{}</a>", clean_text(&label));
+        }
+
+        match self.resolve_note(target) {
+            Some(path) => format!(
+                "<a class=\"{class}\" href=\"{}\">{}</a>",
+                self.note_url(&format!("{path}.md"), heading),
+                clean_text(&label)
+            ),
+            None => format!(
+                "<a class=\"{class} unresolved\" href=\"{}\">{}</a>",
+                self.note_url(&format!("{}.md", target.trim_start_matches('/')), None),
+                clean_text(&label)
+            ),
+        }
+    }
+
+    /// Renders an embedded resource according to its file type.
+    fn render_embed(&self, path: &str, alias: Option<&str>) -> String {
+        let src = self.resource_url(path);
+        let name = clean_text(display_name(path));
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_lowercase())
+            .unwrap_or_default();
+
+        // Obsidian uses the alias of embeds to set their size, e.g. `|300` or `|300x200`
+        let size = alias.and_then(|alias| {
+            let (width, height) = alias.split_once('x').unwrap_or((alias, ""));
+            width.parse::<u32>().ok().map(|width| match height.parse::<u32>() {
+                Ok(height) => format!(" width=\"{width}\" height=\"{height}\""),
+                Err(_) => format!(" width=\"{width}\""),
+            })
+        });
+        let alt = match (alias, &size) {
+            (Some(alias), None) => clean_text(alias),
+            _ => name.clone(),
+        };
+        let size = size.unwrap_or_default();
+
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            format!("<img src=\"{src}\" alt=\"{alt}\"{size}>")
+        } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+            format!("<video src=\"{src}\" controls{size}></video>")
+        } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            format!("<audio src=\"{src}\" controls></audio>")
+        } else {
+            format!("<a class=\"wikilink embed\" href=\"{src}\">{alt}</a>")
+        }
+    }
+}
+
+/// Groups paths by their lowercase file name.
+fn group_by_name(paths: impl IntoIterator<Item = String>) -> HashMap<String, Vec<String>> {
+    let mut grouped: HashMap<String, Vec<String>> = HashMap::new();
+
+    for path in paths {
+        let name = path.rsplit('/').next().unwrap_or(&path).to_lowercase();
+        grouped.entry(name).or_default().push(path);
+    }
+
+    // Prefer the least nested path when several files share a name
+    for paths in grouped.values_mut() {
+        paths.sort_by(|a, b| {
+            a.matches('/')
+                .count()
+                .cmp(&b.matches('/').count())
+                .then_with(|| a.cmp(b))
+        });
+    }
+
+    grouped
+}
+
+/// Finds a path by file name, requiring any folders in `target` to match too.
+fn lookup<'a>(paths: &'a HashMap<String, Vec<String>>, target: &str) -> Option<&'a str> {
+    let target = target.trim().trim_start_matches('/').to_lowercase();
+    let name = target.rsplit('/').next()?;
+    let suffix = format!("/{target}");
+
+    paths
+        .get(name)?
+        .iter()
+        .find(|path| {
+            let path = path.to_lowercase();
+            path == target || path.ends_with(&suffix)
+        })
+        .map(String::as_str)
+}
+
+/// Name of a file without its folders or `.md` extension.
+fn display_name(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(".md").unwrap_or(name)
+}
+
+/// Text a wiki link is displayed with, from its trimmed target, heading and alias.
+fn wiki_link_label(target: &str, heading: Option<&str>, alias: Option<&str>) -> String {
+    match (alias, heading) {
+        (Some(alias), _) => alias.to_string(),
+        (None, Some(heading)) if target.is_empty() => heading.to_string(),
+        (None, Some(heading)) => format!("{} > {heading}", display_name(target)),
+        (None, None) => display_name(target).to_string(),
+    }
+}
+
+/// Replaces the wiki links in text with the text they are displayed with.
+pub(crate) fn wiki_link_text(text: &str) -> Cow<'_, str> {
+    wiki_link_regex().replace_all(text, |captures: &Captures| {
+        let part = |i| {
+            captures.get(i).map(|m| m.as_str().trim()).filter(|part: &&str| !part.is_empty())
+        };
+        wiki_link_label(captures[2].trim(), part(3), part(4))
+    })
+}
+
+/// Matches `[[target#heading|alias]]`, with a leading `!` for embeds.
+pub(crate) fn wiki_link_regex() -> &'static Regex {
+    static WIKI_LINK: OnceLock<Regex> = OnceLock::new();
+    WIKI_LINK.get_or_init(|| {
+        Regex::new(r"(!?)\[\[([^\[\]|#\n]*)(?:#([^\[\]|\n]*))?(?:\|([^\[\]\n]*))?\]\]")
+            .expect("Invalid regex")
+    })
+}
+
+/// Points links, images and HTML `src` attributes to `/resources` at the
+/// resources of the user the note belongs to.
+pub fn resource_links<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    resolver: &LinkResolver,
+) -> Vec<Event<'a>> {
+    let html = |html: CowStr<'a>| -> CowStr<'a> {
+        if html.contains("src=\"/resources") {
+            html.replace("src=\"/resources", &format!("src=\"/{}/resources", resolver.username))
+                .into()
+        } else {
+            html
+        }
+    };
+
+    events
+        .map(|event| match event {
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                let dest_url = resolver.user_resource_url(&dest_url).map_or(dest_url, Into::into);
+                Event::Start(Tag::Link { link_type, dest_url, title, id })
+            }
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                let dest_url = resolver.user_resource_url(&dest_url).map_or(dest_url, Into::into);
+                Event::Start(Tag::Image { link_type, dest_url, title, id })
+            }
+            Event::Html(text) => Event::Html(html(text)),
+            Event::InlineHtml(text) => Event::InlineHtml(html(text)),
+            _ => event,
+        })
+        .collect()
+}
+
+/// Replaces `[[wiki links]]` and `![[embeds]]` in text with HTML.
+///
+/// Code is left untouched.
+pub fn wiki_links<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    resolver: &LinkResolver,
+) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+    let mut in_code_block = false;
+
+    for event in TextMergeStream::new(events) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                output.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                output.push(event);
+            }
+            Event::Text(text) if !in_code_block && text.contains("[[") => {
+                let mut last = 0;
+
+                for captures in wiki_link_regex().captures_iter(&text) {
+                    let link = captures.get(0).expect("Capture group 0 always exists");
+                    if link.start() > last {
+                        output.push(Event::Text(text[last..link.start()].to_string().into()));
+                    }
+
+                    output.push(Event::InlineHtml(render_capture(&captures, resolver).into()));
+                    last = link.end();
+                }
+
+                if last < text.len() {
+                    output.push(Event::Text(text[last..].to_string().into()));
+                }
+            }
+            _ => output.push(event),
+        }
+    }
+
+    output
+}
+
+fn render_capture(captures: &Captures, resolver: &LinkResolver) -> String {
+    resolver.render(
+        !captures[1].is_empty(),
+        &captures[2],
+        captures.get(3).map(|m| m.as_str()),
+        captures.get(4).map(|m| m.as_str()),
+    )
+}
+
+/// Gives every heading without an explicit `{#id}` an id made from its text.
+///
+/// Repeated ids get a numbered suffix, e.g. `notes`, `notes-1` and `notes-2`.
+/// Each heading also ends with an anchor linking to it, which needs the
+/// prefix the sanitizer adds to ids.
+pub fn heading_ids<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    id_prefix: &str,
+) -> Vec<Event<'a>> {
+    let events: Vec<Event> = events.collect();
+
+    // Generated ids never take the place of explicit ones
+    let mut used: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let mut output = Vec::with_capacity(events.len());
+    let mut heading: Option<usize> = None;
+    let mut text = String::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { .. }) => {
+                heading = Some(output.len());
+                text.clear();
+                output.push(event);
+            }
+            // Math is left out of ids whether it was rendered yet or not
+            Event::Text(ref content) | Event::Code(ref content) if heading.is_some() => {
+                text.push_str(&strip_math(content));
+                output.push(event);
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(Event::Start(Tag::Heading { id, .. })) =
+                    heading.take().map(|start| &mut output[start])
+                {
+                    let id = id.get_or_insert_with(|| {
+                        unique_id(&wiki_link_text(&text), &mut used).into()
+                    });
+                    let anchor = format!(
+                        "<a class=\"heading-anchor\" href=\"#{}{}\">#</a>",
+                        clean_text(id_prefix),
+                        clean_text(id)
+                    );
+                    output.push(Event::InlineHtml(anchor.into()));
+                }
+                output.push(event);
+            }
+            _ => output.push(event),
+        }
+    }
+
+    output
+}
+
+/// Slug of a heading's text which isn't in `used` yet, adding it to `used`.
+fn unique_id(text: &str, used: &mut HashSet<String>) -> String {
+    let slug = match slugify(text) {
+        slug if slug.is_empty() => "section".to_string(),
+        slug => slug,
+    };
+
+    let mut id = slug.clone();
+    let mut i = 0;
+    while !used.insert(id.clone()) {
+        i += 1;
+        id = format!("{slug}-{i}");
+    }
+    id
+}
+
+/// Builds the table of contents of a note from events passed through
+/// [`heading_ids`], with `id_prefix` added to the ids like the sanitizer does.
+///
+/// Headings are nested below the closest heading before them with a lower
+/// level, so skipped levels such as an h4 right below an h1 still nest.
+pub fn table_of_contents(events: &[Event], id_prefix: &str) -> Vec<TocHeading> {
+    let mut headings = Vec::new();
+    let mut current: Option<TocHeading> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some(TocHeading {
+                    level: *level as u8,
+                    id: format!("{id_prefix}{}", id.as_deref().unwrap_or_default()),
+                    text: String::new(),
+                    children: Vec::new(),
+                });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = current.as_mut() {
+                    heading.text.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(mut heading) = current.take() {
+                    heading.text = wiki_link_text(&heading.text).trim().to_string();
+                    headings.push(heading);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    nest_headings(&mut headings.into_iter().peekable(), 0)
+}
+
+/// Takes the headings below `level`, each with the deeper headings after it
+/// as its children.
+fn nest_headings(
+    headings: &mut Peekable<impl Iterator<Item = TocHeading>>,
+    level: u8,
+) -> Vec<TocHeading> {
+    let mut nested = Vec::new();
+
+    while let Some(mut heading) = headings.next_if(|heading| heading.level > level) {
+        heading.children = nest_headings(headings, heading.level);
+        nested.push(heading);
+    }
+
+    nested
+}
+
+/// The `[!type]` marker at the start of a callout.
+#[derive(Debug, PartialEq)]
+struct Callout<'t> {
+    kind: &'static str,
+    /// Type as written, the title if none is given
+    name: &'t str,
+    /// Whether a foldable callout starts open, `None` if it can't be folded
+    open: Option<bool>,
+    /// Rest of the first line
+    title: &'t str,
+}
+
+impl<'t> Callout<'t> {
+    fn parse(text: &'t str) -> Option<Self> {
+        static MARKER: OnceLock<Regex> = OnceLock::new();
+        let marker =
+            MARKER.get_or_init(|| Regex::new(r"^\[!([\w-]+)\]([+-]?)").expect("Invalid regex"));
+
+        let captures = marker.captures(text)?;
+        let name = captures.get(1).expect("Capture group 1 always exists").as_str();
+        let kind = CALLOUT_KINDS
+            .iter()
+            .find(|(kind, aliases)| {
+                once(kind).chain(aliases.iter()).any(|alias| alias.eq_ignore_ascii_case(name))
+            })
+            .map_or("note", |(kind, _)| *kind);
+
+        Some(Self {
+            kind,
+            name,
+            open: match &captures[2] {
+                "-" => Some(false),
+                "+" => Some(true),
+                _ => None,
+            },
+            title: text[captures[0].len()..].trim_start(),
+        })
+    }
+
+    /// Tags opening the callout and its title.
+    fn start(&self) -> String {
+        let kind = self.kind;
+        match self.open {
+            Some(open) => format!(
+                "<details class=\"callout\" data-callout=\"{kind}\"{}>\
+                 <summary class=\"callout-title\">",
+                if open { " open" } else { "" }
+            ),
+            None => format!(
+                "<aside class=\"callout\" data-callout=\"{kind}\"><p class=\"callout-title\">"
+            ),
+        }
+    }
+
+    /// Tags closing the title and opening the content of the callout.
+    fn content(&self) -> &'static str {
+        match self.open {
+            Some(_) => "</summary><div class=\"callout-content\">\n",
+            None => "</p><div class=\"callout-content\">\n",
+        }
+    }
+
+    fn end(&self) -> &'static str {
+        match self.open {
+            Some(_) => "</div></details>\n",
+            None => "</div></aside>\n",
+        }
+    }
+
+    /// The type as written with its first letter in uppercase, e.g. `Warning`.
+    fn default_title(&self) -> String {
+        let mut chars = self.name.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Turns block quotes starting with a `[!type]` marker into callouts, such as
+/// GitHub's `> [!WARNING]` or Obsidian's foldable `> [!tip]- Title`.
+///
+/// The rest of the marker's line is the title, which defaults to the type. A
+/// `-` after the marker makes the callout start folded and a `+` makes it
+/// foldable but open. Unknown types are shown as notes.
+pub fn callouts<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut events = TextMergeStream::new(events).collect::<Vec<_>>().into_iter();
+    let mut output = Vec::new();
+    // How each block quote being rendered ends, `None` for plain quotes
+    let mut quotes: Vec<Option<&'static str>> = Vec::new();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::BlockQuote) => {
+                let callout = match events.as_slice() {
+                    [Event::Start(Tag::Paragraph), Event::Text(text), ..] => Callout::parse(text),
+                    _ => None,
+                };
+                let Some(callout) = callout else {
+                    quotes.push(None);
+                    output.push(event);
+                    continue;
+                };
+
+                quotes.push(Some(callout.end()));
+                output.push(Event::Html(callout.start().into()));
+                let title_ended = matches!(
+                    events.as_slice().get(2),
+                    None | Some(Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph))
+                );
+                // Spaces before the formatted part of a title are kept
+                let title = match title_ended {
+                    true => callout.title.trim_end(),
+                    false => callout.title,
+                };
+                if !title.is_empty() {
+                    output.push(Event::Text(title.to_string().into()));
+                } else if title_ended {
+                    output.push(Event::Text(callout.default_title().into()));
+                }
+                let content = callout.content();
+
+                // Skip the start of the paragraph and the marker
+                events.next();
+                events.next();
+
+                // The rest of the first line belongs to the title
+                loop {
+                    match events.next() {
+                        Some(Event::SoftBreak | Event::HardBreak) => {
+                            output.push(Event::Html(content.into()));
+                            output.push(Event::Start(Tag::Paragraph));
+                            break;
+                        }
+                        Some(Event::End(TagEnd::Paragraph)) | None => {
+                            output.push(Event::Html(content.into()));
+                            break;
+                        }
+                        Some(event) => output.push(event),
+                    }
+                }
+            }
+            Event::End(TagEnd::BlockQuote) => match quotes.pop().flatten() {
+                Some(end) => output.push(Event::Html(end.into())),
+                None => output.push(event),
+            },
+            _ => output.push(event),
+        }
+    }
+
+    output
+}
+
+/// Byte offsets of the `[ ]` and `[x]` markers of the task list items in a
+/// note, including any frontmatter.
+///
+/// The note is parsed with its math taken out, exactly like it is rendered, so
+/// math can't make these disagree with the rendered checkboxes.
+pub fn task_offsets(note: &str) -> Vec<usize> {
+    let (_, body) = split_frontmatter(note);
+    let start = note.len() - body.len();
+    let (body, _, source) = extract_math(body);
+
+    Parser::new_ext(&body, Options::all())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::TaskListMarker(_)))
+        .map(|(_, range)| start + source.original(range.start))
+        .collect()
+}
+
+/// Checks or unchecks the task list item whose marker is at `offset`, as
+/// returned by [`task_offsets`]. Returns `None` if there is no task there.
+pub fn set_task(note: &str, offset: usize, checked: bool) -> Option<String> {
+    if !task_offsets(note).contains(&offset) {
+        return None;
+    }
+
+    let marker = if checked { "[x]" } else { "[ ]" };
+    let mut note = note.to_string();
+    note.replace_range(offset..offset + marker.len(), marker);
+    Some(note)
+}
+
+/// Renders task list markers as checkboxes, each with the offset of its
+/// marker in the note from `offsets` so it can be toggled.
+///
+/// Checkboxes start out disabled, and are only enabled in the browser if they
+/// have an offset.
+pub fn task_checkboxes<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    offsets: &[usize],
+) -> Vec<Event<'a>> {
+    let mut offsets = offsets.iter();
+
+    events
+        .map(|event| match event {
+            Event::TaskListMarker(checked) => {
+                let offset = offsets
+                    .next()
+                    .map(|offset| format!(" data-offset=\"{offset}\""))
+                    .unwrap_or_default();
+                let checked = if checked { " checked" } else { "" };
+                Event::InlineHtml(
+                    format!("<input type=\"checkbox\" class=\"task\"{offset}{checked} disabled>")
+                        .into(),
+                )
+            }
+            _ => event,
+        })
+        .collect()
+}
+
+/// Marks where math was taken out of a note, around the index of the formula.
+const MATH_START: char = '\u{E000}';
+const MATH_END: char = '\u{E001}';
+
+/// Maps offsets in the text left by [`extract_math`] back to the note the math
+/// was taken out of.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    /// Offsets right after every placeholder, in the text and in the note
+    shifts: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    /// Offset in the note of the text at `offset`, which must not be inside a
+    /// placeholder.
+    pub fn original(&self, offset: usize) -> usize {
+        match self.shifts.partition_point(|&(text, _)| text <= offset) {
+            0 => offset,
+            i => {
+                let (text, note) = self.shifts[i - 1];
+                note + offset - text
+            }
+        }
+    }
+}
+
+/// A LaTeX formula taken out of a note before parsing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Math {
+    pub latex: String,
+    pub display: bool,
+}
+
+impl Math {
+    /// Renders the formula to MathML, or to its source if it is invalid.
+    pub fn to_html(&self) -> String {
+        let style = if self.display { DisplayStyle::Block } else { DisplayStyle::Inline };
+
+        match latex_to_mathml(&self.latex, style) {
+            Ok(mathml) => mathml,
+            Err(_) => {
+                let delimiter = if self.display { "$$" } else { "$" };
+                format!(
+                    "<code class=\"math-error\">{}</code>",
+                    clean_text(&format!("{delimiter}{}{delimiter}", self.latex))
+                )
+            }
+        }
+    }
+}
+
+/// Byte ranges of code and raw HTML, where markdown extensions don't apply.
+pub(crate) fn literal_ranges(md: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(md, Options::all())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock)
+            | Event::Code(_)
+            | Event::Html(_)
+            | Event::InlineHtml(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replaces `$inline$` and `$$display$$` math with placeholders, returning
+/// where the rest of the note moved along with the formulas.
+///
+/// Math has to be taken out before parsing as markdown would otherwise
+/// turn e.g. `$a_1 * b_2 * c$` into emphasis. Code and HTML are skipped,
+/// as are escaped dollar signs. Inline math may not start or end with
+/// whitespace, so `$5 or $10` is left alone.
+pub fn extract_math(md: &str) -> (String, Vec<Math>, SourceMap) {
+    let mut output = String::with_capacity(md.len());
+    let mut math = Vec::new();
+    let mut source = SourceMap::default();
+    let mut skipped = literal_ranges(md).into_iter().peekable();
+    let mut last = 0;
+    let mut i = 0;
+
+    while let Some(offset) = md[i..].find('$') {
+        let start = i + offset;
+
+        // Jump over code preceding the dollar sign
+        while skipped.next_if(|range| range.end <= start).is_some() {}
+        if let Some(range) = skipped.peek().filter(|range| range.start <= start) {
+            i = range.end;
+            continue;
+        }
+
+        let limit = skipped.peek().map_or(md.len(), |range| range.start);
+        let found = if md[..start].ends_with('\\') {
+            None
+        } else if md[start..].starts_with("$$") {
+            display_math(&md[start..limit])
+        } else {
+            inline_math(&md[start..limit])
+        };
+
+        match found {
+            Some((latex, len)) => {
+                output.push_str(&md[last..start]);
+                output.push(MATH_START);
+                output.push_str(&math.len().to_string());
+                output.push(MATH_END);
+
+                math.push(Math {
+                    latex: latex.trim().to_string(),
+                    display: md[start..].starts_with("$$"),
+                });
+                last = start + len;
+                i = last;
+                source.shifts.push((output.len(), last));
+            }
+            None => i = start + if md[start..].starts_with("$$") { 2 } else { 1 },
+        }
+    }
+
+    output.push_str(&md[last..]);
+    (output, math, source)
+}
+
+/// Returns the formula and the length of `$$...$$` at the start of `text`.
+fn display_math(text: &str) -> Option<(&str, usize)> {
+    let end = text[2..].find("$$")? + 2;
+    let latex = &text[2..end];
+    (!latex.trim().is_empty()).then_some((latex, end + 2))
+}
+
+/// Returns the formula and the length of `$...$` at the start of `text`.
+fn inline_math(text: &str) -> Option<(&str, usize)> {
+    if text[1..].starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let bytes = text.as_bytes();
+    let mut end = 1;
+    while end < text.len() {
+        match bytes[end] {
+            b'\\' => end += 1,
+            // Math does not continue past the end of a paragraph
+            b'\n' if text[end + 1..].trim_start_matches([' ', '\t']).starts_with('\n') => {
+                return None
+            }
+            b'$' if end > 1
+                && !bytes[end - 1].is_ascii_whitespace()
+                && !bytes.get(end + 1).is_some_and(u8::is_ascii_digit) =>
+            {
+                return Some((&text[1..end], end + 1));
+            }
+            _ => (),
+        }
+        end += 1;
+    }
+
+    None
+}
+
+/// Matches the placeholders left by [`extract_math`].
+fn math_placeholder_regex() -> &'static Regex {
+    static MATH_PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    MATH_PLACEHOLDER.get_or_init(|| {
+        Regex::new(&format!("{MATH_START}([0-9]+){MATH_END}")).expect("Invalid regex")
+    })
+}
+
+/// Removes the placeholders left by [`extract_math`] from text.
+pub fn strip_math(text: &str) -> Cow<'_, str> {
+    math_placeholder_regex().replace_all(text, "")
+}
+
+/// Replaces the placeholders left by [`extract_math`] in text with the LaTeX
+/// of their formulas.
+pub fn math_source<'a>(text: &'a str, math: &[Math]) -> Cow<'a, str> {
+    math_placeholder_regex().replace_all(text, |captures: &Captures| {
+        captures[1]
+            .parse()
+            .ok()
+            .and_then(|i: usize| math.get(i))
+            .map_or_else(String::new, |formula| formula.latex.clone())
+    })
+}
+
+/// Replaces the placeholders left by [`extract_math`] with MathML.
+pub fn render_math<'a>(events: impl Iterator<Item = Event<'a>>, math: &[Math]) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+
+    for event in TextMergeStream::new(events) {
+        match event {
+            Event::Text(text) if text.contains(MATH_START) => {
+                let mut last = 0;
+
+                for captures in math_placeholder_regex().captures_iter(&text) {
+                    let placeholder = captures.get(0).expect("Capture group 0 always exists");
+                    let Some(formula) = captures[1].parse().ok().and_then(|i: usize| math.get(i))
+                    else {
+                        continue;
+                    };
+
+                    if placeholder.start() > last {
+                        output.push(Event::Text(text[last..placeholder.start()].to_string().into()));
+                    }
+
+                    output.push(Event::InlineHtml(formula.to_html().into()));
+                    last = placeholder.end();
+                }
+
+                if last < text.len() {
+                    output.push(Event::Text(text[last..].to_string().into()));
+                }
+            }
+            _ => output.push(event),
+        }
+    }
+
+    output
+}
+
+/// Options of a fenced code block parsed from its info string.
+///
+/// The info string starts with the language and may be followed by
+/// attributes, e.g. `rust {3-5,8} linenos`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CodeBlockInfo {
+    pub language: String,
+    pub line_numbers: bool,
+    /// Inclusive ranges of lines to highlight, counting from 1
+    pub highlighted: Vec<(usize, usize)>,
+}
+
+impl CodeBlockInfo {
+    pub fn parse(info: &str) -> Self {
+        let info = info.trim();
+        let split = info.find(|c: char| c.is_whitespace() || c == '{').unwrap_or(info.len());
+        let (language, attributes) = info.split_at(split);
+
+        let mut block = Self {
+            language: language.to_string(),
+            ..Default::default()
+        };
+
+        let attributes = attributes.split(|c: char| c.is_whitespace() || "{},".contains(c));
+        for attribute in attributes.filter(|attribute| !attribute.is_empty()) {
+            if LINE_NUMBER_ATTRIBUTES.contains(&attribute) {
+                block.line_numbers = true;
+                continue;
+            }
+
+            let (start, end) = attribute.split_once('-').unwrap_or((attribute, attribute));
+            if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                block.highlighted.push((start, end));
+            }
+        }
+
+        block
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&line))
+    }
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Highlights code with classes prefixed by [`HIGHLIGHT_CLASS_PREFIX`].
+///
+/// Each line is wrapped in its own span so lines can be numbered and marked.
+pub fn highlight_code(code: &str, info: &CodeBlockInfo) -> String {
+    let syntaxes = syntax_set();
+    let syntax = syntaxes
+        .find_syntax_by_token(&info.language)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(
+        syntax,
+        syntaxes,
+        ClassStyle::SpacedPrefixed {
+            prefix: HIGHLIGHT_CLASS_PREFIX,
+        },
+    );
+
+    let highlighted = LinesWithEndings::from(code)
+        .try_for_each(|line| generator.parse_html_for_line_which_includes_newline(line))
+        .map(|_| generator.finalize())
+        .unwrap_or_else(|_| clean_text(code));
+
+    let mut html = match info.line_numbers {
+        true => String::from("<pre class=\"highlight numbered\"><code>"),
+        false => String::from("<pre class=\"highlight\"><code>"),
+    };
+
+    for (number, line) in split_lines(&highlighted).into_iter().enumerate() {
+        let class = match info.is_highlighted(number + 1) {
+            true => "hl-line hl-marked",
+            false => "hl-line",
+        };
+        html.push_str(&format!("<span class=\"{class}\">{line}\n</span>"));
+    }
+
+    html.push_str("</code></pre>\n");
+    html
+}
+
+/// Splits highlighted HTML into lines, closing and reopening spans that
+/// continue over a line break.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with("<span") {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            open.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some(after) = rest.strip_prefix("</span>") {
+            open.pop();
+            line.push_str("</span>");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('\n') {
+            line.push_str(&"</span>".repeat(open.len()));
+            lines.push(take(&mut line));
+            line.extend(open.iter().copied());
+            has_text = false;
+            rest = after;
+        } else {
+            let end = rest.find(['<', '\n']).unwrap_or(rest.len());
+            line.push_str(&rest[..end]);
+            has_text = true;
+            rest = &rest[end..];
+        }
+    }
+
+    if has_text {
+        line.push_str(&"</span>".repeat(open.len()));
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Replaces code blocks with syntax highlighted HTML.
+pub fn highlight_code_blocks<'a>(events: impl IntoIterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+    let mut code_block: Option<(CodeBlockInfo, String)> = None;
+
+    for event in events {
+        match (event, &mut code_block) {
+            (Event::Start(Tag::CodeBlock(kind)), None) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(&info),
+                    CodeBlockKind::Indented => CodeBlockInfo::default(),
+                };
+                code_block = Some((info, String::new()));
+            }
+            (Event::Text(text), Some((_, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+                let (info, code) = code_block.take().expect("Code block was started");
+                output.push(Event::Html(highlight_code(&code, &info).into()));
+            }
+            (event, _) => output.push(event),
+        }
+    }
+
+    output
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::api::NoteMetadata;
+    use crate::api::TocHeading;
+    use crate::markdown::{
+        callouts, extract_math, heading_ids, highlight_code, highlight_code_blocks, render_math,
+        set_task, slugify, split_frontmatter, table_of_contents, task_checkboxes, task_offsets,
+        wiki_links, CodeBlockInfo, LinkResolver, Math,
+    };
+    use pulldown_cmark::{html, Options, Parser};
+
+    fn resolver() -> LinkResolver {
+        LinkResolver::new(
+            "bob",
+            [
+                "index.md".to_string(),
+                "projects/Alpha Plan.md".to_string(),
+                "archive/projects/Alpha Plan.md".to_string(),
+                "daily/2024-04-01.md".to_string(),
+            ],
+            ["images/diagram.png".to_string(), "talk.mp4".to_string()],
+        )
+    }
+
+    fn render(md: &str) -> String {
+        let events = wiki_links(Parser::new_ext(md, Options::all()), &resolver());
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+        output
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Multiple   spaces -- and_dashes "), "multiple-spaces-and-dashes");
+        assert_eq!(slugify("Ünïcödé Heading 2"), "ünïcödé-heading-2");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    #[test]
+    fn resolution() {
+        let resolver = resolver();
+        assert_eq!(resolver.resolve_note("alpha plan"), Some("projects/Alpha Plan"));
+        assert_eq!(
+            resolver.resolve_note("archive/projects/Alpha Plan.md"),
+            Some("archive/projects/Alpha Plan")
+        );
+        assert_eq!(resolver.resolve_note("missing"), None);
+        assert_eq!(resolver.resolve_resource("Diagram.png"), Some("images/diagram.png"));
+    }
+
+    #[test]
+    fn alias_resolution() {
+        let aliases = ["Roadmap".to_string(), "index".to_string()];
+        let resolver = resolver().with_aliases([("projects/Alpha Plan.md", aliases.as_slice())]);
+
+        assert_eq!(resolver.resolve_note("roadmap"), Some("projects/Alpha Plan"));
+        // Notes take precedence over aliases
+        assert_eq!(resolver.resolve_note("Index"), Some("index"));
+        assert_eq!(resolver.resolve_note("missing"), None);
+    }
+
+    #[test]
+    fn fingerprints() {
+        let aliases = ["Roadmap".to_string()];
+        let with_alias = resolver().with_aliases([("index.md", aliases.as_slice())]);
+        let moved = LinkResolver::new(
+            "bob",
+            ["index.md".to_string(), "Alpha Plan.md".to_string()],
+            [],
+        );
+
+        assert_eq!(resolver().fingerprint(), resolver().fingerprint());
+        assert_ne!(resolver().fingerprint(), with_alias.fingerprint());
+        assert_ne!(resolver().fingerprint(), moved.fingerprint());
+
+        let prefixed = resolver().with_id_prefix("note-");
+        assert_ne!(resolver().fingerprint(), prefixed.fingerprint());
+        assert_eq!(
+            prefixed.note_url("index.md", Some("Next steps")),
+            "/bob/notes/index.md#note-next-steps"
+        );
+    }
+
+    #[test]
+    fn frontmatter() {
+        let (metadata, body) = split_frontmatter(
+            "---\ntitle: Release plan\ntags: [work, \"#planning/q3\"]\naliases: Roadmap, Plan\n---\n# Body\n",
+        );
+        assert_eq!(
+            metadata,
+            NoteMetadata {
+                title: Some("Release plan".into()),
+                tags: vec!["work".into(), "planning/q3".into()],
+                aliases: vec!["Roadmap".into(), "Plan".into()],
+            }
+        );
+        assert_eq!(body, "# Body\n");
+
+        let (metadata, body) =
+            split_frontmatter("+++\ntitle = \"Toml\"\ntags = \"a b\"\n+++\n\nText");
+        assert_eq!(metadata.title.as_deref(), Some("Toml"));
+        assert_eq!(metadata.tags, vec!["a", "b"]);
+        assert_eq!(body, "\nText");
+
+        // Invalid frontmatter is still removed
+        let (metadata, body) = split_frontmatter("---\ntitle: [unclosed\n...\nText");
+        assert_eq!(metadata, NoteMetadata::default());
+        assert_eq!(body, "Text");
+
+        for text in ["Text\n---\ntitle: x\n---\n", "---\nNo closing delimiter", "--- \n"] {
+            assert_eq!(split_frontmatter(text), (NoteMetadata::default(), text));
+        }
+    }
+
+    #[test]
+    fn code_block_info() {
+        assert_eq!(CodeBlockInfo::parse(""), CodeBlockInfo::default());
+        assert_eq!(
+            CodeBlockInfo::parse("rust {3-5,8} linenos"),
+            CodeBlockInfo {
+                language: "rust".into(),
+                line_numbers: true,
+                highlighted: vec![(3, 5), (8, 8)],
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("py{2}"),
+            CodeBlockInfo {
+                language: "py".into(),
+                line_numbers: false,
+                highlighted: vec![(2, 2)],
+            }
+        );
+    }
+
+    #[test]
+    fn code_highlighting() {
+        let info = CodeBlockInfo::parse("rust {2} linenos");
+        let html = highlight_code("/* a\nb */\nlet x = 1;\n", &info);
+
+        assert!(html.starts_with("<pre class=\"highlight numbered\"><code>"));
+        assert_eq!(html.matches("<span class=\"hl-line").count(), 3);
+        assert_eq!(html.matches("hl-marked").count(), 1);
+        assert!(html.contains("hl-comment"));
+        assert!(html.contains("hl-keyword"));
+        assert_eq!(html.matches("<span").count(), html.matches("</span>").count());
+
+        // Block comments spanning lines are reopened on the next line
+        let second_line = html.split("hl-marked\">").nth(1).unwrap();
+        assert!(second_line.starts_with("<span class=\"hl-source hl-rust\"><span class=\"hl-comment"));
+    }
+
+    #[test]
+    fn unknown_languages() {
+        let events = highlight_code_blocks(Parser::new("```nonsense\n<b>\n```\n"));
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+
+        assert_eq!(
+            output,
+            "<pre class=\"highlight\"><code><span class=\"hl-line\"><span class=\"hl-text hl-plain\">&lt;b&gt;</span>\n</span></code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn wiki_link_rendering() {
+        assert_eq!(
+            render("See [[Alpha Plan]]."),
+            "<p>See <a class=\"wikilink\" href=\"/bob/notes/projects/Alpha%20Plan.md\">Alpha&#32;Plan</a>.</p>\n"
+        );
+        assert_eq!(
+            render("[[daily/2024-04-01#Morning notes|today]]"),
+            "<p><a class=\"wikilink\" href=\"/bob/notes/daily/2024-04-01.md#ln-morning-notes\">today</a></p>\n"
+        );
+        assert_eq!(
+            render("[[Nowhere]]"),
+            "<p><a class=\"wikilink unresolved\" href=\"/bob/notes/Nowhere.md\">Nowhere</a></p>\n"
+        );
+        assert_eq!(
+            render("![[diagram.png|300]]"),
+            "<p><img src=\"/bob/resources/images/diagram.png\" alt=\"diagram.png\" width=\"300\"></p>\n"
+        );
+        assert_eq!(
+            render("`[[Alpha Plan]]`\n\n```\n[[Alpha Plan]]\n```\n"),
+            "<p><code>[[Alpha Plan]]</code></p>\n<pre><code>[[Alpha Plan]]\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn math_extraction() {
+        let math = |latex: &str, display| Math {
+            latex: latex.to_string(),
+            display,
+        };
+
+        let (md, found, _) =
+            extract_math("Euler: $e^{i\\pi} + 1 = 0$.\n\n$$\n\\int_0^1 x\\,dx\n$$\n");
+        assert_eq!(md, "Euler: \u{E000}0\u{E001}.\n\n\u{E000}1\u{E001}\n");
+        assert_eq!(
+            found,
+            vec![math("e^{i\\pi} + 1 = 0", false), math("\\int_0^1 x\\,dx", true)]
+        );
+
+        for text in [
+            "It costs $5 or $10.",
+            "Escaped \\$x$ dollars",
+            "`$x$` and\n\n```\n$$x$$\n```\n",
+            "$x\n\ny$",
+            "$ x $",
+        ] {
+            let (md, found, source) = extract_math(text);
+            assert_eq!((md, found), (text.to_string(), vec![]), "{text}");
+            assert_eq!(source.original(4), 4);
+        }
+
+        let note = "$a_1 * b_2 * c$ and `$` then $x$";
+        let (md, found, source) = extract_math(note);
+        assert_eq!(md, "\u{E000}0\u{E001} and `$` then \u{E000}1\u{E001}");
+        assert_eq!(found, vec![math("a_1 * b_2 * c", false), math("x", false)]);
+
+        // Offsets past a formula point at the same text in the note
+        let then = md.find("then").unwrap();
+        assert_eq!(&note[source.original(then)..][..4], "then");
+        assert_eq!(source.original(2), 2);
+    }
+
+    #[test]
+    fn math_rendering() {
+        let (md, math, _) = extract_math("Area $r^2$ and $\\frac{1$\n\n$$x^2$$");
+        let events = render_math(Parser::new_ext(&md, Options::all()), &math);
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+
+        assert!(output.starts_with("<p>Area <math"));
+        assert!(output.contains("display=\"inline\""));
+        assert!(output.contains("display=\"block\""));
+        assert!(output.contains("<code class=\"math-error\">$\\frac{1$</code>"));
+        assert!(!output.contains('\u{E000}'));
+    }
+
+    fn ids(md: &str) -> String {
+        let events = heading_ids(Parser::new_ext(md, Options::all()), "ln-");
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+        output
+    }
+
+    #[test]
+    fn heading_id_generation() {
+        assert_eq!(
+            ids("# Hello, *World*!"),
+            "<h1 id=\"hello-world\">Hello, <em>World</em>!<a class=\"heading-anchor\" href=\"#ln-hello-world\">#</a></h1>\n"
+        );
+        assert_eq!(
+            ids("## `cargo test` [[projects/Alpha Plan#Goals|plan]]"),
+            "<h2 id=\"cargo-test-plan\"><code>cargo test</code> [[projects/Alpha Plan#Goals|plan]]<a class=\"heading-anchor\" href=\"#ln-cargo-test-plan\">#</a></h2>\n"
+        );
+        assert!(ids("# ***").contains("id=\"section\""));
+        assert!(ids("Not a heading").starts_with("<p>"));
+    }
+
+    #[test]
+    fn duplicate_heading_ids() {
+        let output = ids("# Notes\n## Notes\n### Notes\n# Other {#notes-1}\n");
+
+        assert!(output.contains("<h1 id=\"notes\">"));
+        assert!(output.contains("<h2 id=\"notes-2\">"));
+        assert!(output.contains("<h3 id=\"notes-3\">"));
+        // Explicit ids are kept and never reused
+        assert!(output.contains("<h1 id=\"notes-1\">"));
+        assert!(output.contains("href=\"#ln-notes-1\""));
+        assert_eq!(output.matches("id=\"notes-1\"").count(), 1);
+    }
+
+    #[test]
+    fn toc_generation() {
+        let toc = |md: &str| {
+            let events = heading_ids(Parser::new_ext(md, Options::all()), "ln-");
+            table_of_contents(&events, "ln-")
+        };
+        let heading = |level, id: &str, text: &str, children| TocHeading {
+            level,
+            id: id.to_string(),
+            text: text.to_string(),
+            children,
+        };
+
+        assert_eq!(toc("No headings"), vec![]);
+        assert_eq!(
+            toc("## Hello *world*, `code` and [[Alpha Plan|plans]]"),
+            vec![heading(
+                2,
+                "ln-hello-world-code-and-plans",
+                "Hello world, code and plans",
+                vec![]
+            )]
+        );
+        assert_eq!(
+            toc("# Title {#top}\n## A\n### A.1\n## B\n# Next\n"),
+            vec![
+                heading(
+                    1,
+                    "ln-top",
+                    "Title",
+                    vec![
+                        heading(2, "ln-a", "A", vec![heading(3, "ln-a1", "A.1", vec![])]),
+                        heading(2, "ln-b", "B", vec![]),
+                    ]
+                ),
+                heading(1, "ln-next", "Next", vec![]),
+            ]
+        );
+
+        // Skipped levels nest below the closest higher heading
+        assert_eq!(
+            toc("# One\n#### Deep\n## Two\n"),
+            vec![heading(
+                1,
+                "ln-one",
+                "One",
+                vec![
+                    heading(4, "ln-deep", "Deep", vec![]),
+                    heading(2, "ln-two", "Two", vec![]),
+                ]
+            )]
+        );
+
+        // Headings before the first top level heading stay at the top
+        assert_eq!(
+            toc("### Intro\n# Main\n"),
+            vec![
+                heading(3, "ln-intro", "Intro", vec![]),
+                heading(1, "ln-main", "Main", vec![]),
+            ]
+        );
+    }
+
+    fn callout(md: &str) -> String {
+        let mut output = String::new();
+        html::push_html(
+            &mut output,
+            callouts(Parser::new_ext(md, Options::all())).into_iter(),
+        );
+        output
+    }
+
+    #[test]
+    fn callout_rendering() {
+        assert_eq!(
+            callout("> [!WARNING]\n> Restart the *database* first."),
+            "<aside class=\"callout\" data-callout=\"warning\"><p class=\"callout-title\">Warning</p>\
+             <div class=\"callout-content\">\n<p>Restart the <em>database</em> first.</p>\n</div></aside>\n"
+        );
+        assert_eq!(
+            callout("> [!tip]- Foldable *title*\n> Hidden\n\n> [!faq]+\n"),
+            "<details class=\"callout\" data-callout=\"tip\"><summary class=\"callout-title\">Foldable <em>title</em></summary>\
+             <div class=\"callout-content\">\n<p>Hidden</p>\n</div></details>\n\
+             <details class=\"callout\" data-callout=\"question\" open><summary class=\"callout-title\">Faq</summary>\
+             <div class=\"callout-content\">\n</div></details>\n"
+        );
+        // Unknown types are notes, nested quotes and callouts still work
+        assert_eq!(
+            callout("> [!custom] Title\n>\n> > [!caution]\n> > Careful\n"),
+            "<aside class=\"callout\" data-callout=\"note\"><p class=\"callout-title\">Title</p>\
+             <div class=\"callout-content\">\n<aside class=\"callout\" data-callout=\"warning\"><p class=\"callout-title\">Caution</p>\
+             <div class=\"callout-content\">\n<p>Careful</p>\n</div></aside>\n</div></aside>\n"
+        );
+        assert_eq!(
+            callout("> Just a quote [!NOTE]\n\n> [!NOTE\n"),
+            "<blockquote>\n<p>Just a quote [!NOTE]</p>\n</blockquote>\n<blockquote>\n<p>[!NOTE</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn task_toggling() {
+        let note =
+            "---\ntitle: Deploy\n---\n- [ ] Backup\n- [x] Upgrade\n\n```\n- [ ] Not a task\n```\n";
+        let offsets = task_offsets(note);
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(&note[offsets[0]..offsets[0] + 3], "[ ]");
+        assert_eq!(&note[offsets[1]..offsets[1] + 3], "[x]");
+
+        assert_eq!(
+            set_task(note, offsets[0], true).unwrap(),
+            note.replacen("[ ] Backup", "[x] Backup", 1)
+        );
+        assert_eq!(
+            set_task(note, offsets[1], false).unwrap(),
+            note.replacen("[x] Upgrade", "[ ] Upgrade", 1)
+        );
+        assert_eq!(set_task(note, offsets[0], false).unwrap(), note);
+
+        // Math can hide list markers and ends inside tasks
+        let note = "$$\n- [ ] Not a task\n$$\n\n- [ ] Pay $5 or $x$ later\n- [ ] Renew\n";
+        let offsets = task_offsets(note);
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(&note[offsets[1]..offsets[1] + 9], "[ ] Renew");
+
+        // Only task markers can be changed
+        let code = note.find("[ ] Not").unwrap();
+        assert_eq!(set_task(note, code, true), None);
+        assert_eq!(set_task(note, offsets[0] + 1, true), None);
+        assert_eq!(set_task(note, note.len() + 10, true), None);
+    }
+
+    #[test]
+    fn task_checkbox_rendering() {
+        let render = |md: &str, offsets: &[usize]| {
+            let events = task_checkboxes(Parser::new_ext(md, Options::all()), offsets);
+            let mut output = String::new();
+            html::push_html(&mut output, events.into_iter());
+            output
+        };
+
+        assert_eq!(
+            render("- [ ] a\n- [x] b\n", &[2, 10]),
+            "<ul>\n<li><input type=\"checkbox\" class=\"task\" data-offset=\"2\" disabled>a</li>\n\
+             <li><input type=\"checkbox\" class=\"task\" data-offset=\"10\" checked disabled>b</li>\n</ul>\n"
+        );
+        // Previews have no file to write back to
+        assert_eq!(
+            render("- [x] a\n", &[]),
+            "<ul>\n<li><input type=\"checkbox\" class=\"task\" checked disabled>a</li>\n</ul>\n"
+        );
+    }
+}
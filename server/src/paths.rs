@@ -0,0 +1,308 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// How symlinks inside a user's folders are treated.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Follow symlinks as long as they point somewhere inside the folder
+    #[default]
+    WithinRoot,
+    /// Refuse any path going through a symlink
+    Deny,
+    /// Follow symlinks wherever they point
+    Allow,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "within_root" => Ok(SymlinkPolicy::WithinRoot),
+            "deny" => Ok(SymlinkPolicy::Deny),
+            "allow" => Ok(SymlinkPolicy::Allow),
+            other => Err(format!("expected within_root, deny or allow, got \"{other}\"")),
+        }
+    }
+}
+
+/// Resolves a path relative to `root`, such as a note below a notes directory.
+///
+/// Absolute paths and `..` are rejected with `InvalidInput` and symlinks are
+/// checked against `policy`, failing with `PermissionDenied` when they are
+/// not allowed. The path doesn't have to exist, so new files can be resolved
+/// too. The resolved path is canonical, with every symlink followed, so it
+/// can be used without the checked symlinks being swapped out in between.
+pub fn resolve(root: &Path, path: &str, policy: SymlinkPolicy) -> std::io::Result<PathBuf> {
+    let mut names = Vec::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) if !name.to_string_lossy().contains('\0') => names.push(name),
+            Component::CurDir => {}
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid path")),
+        }
+    }
+
+    let resolved: PathBuf = names.iter().fold(root.to_path_buf(), |path, name| path.join(name));
+    let real = canonical(&resolved);
+
+    match policy {
+        SymlinkPolicy::Allow => Ok(real),
+        SymlinkPolicy::Deny => {
+            let mut current = root.to_path_buf();
+            for name in names {
+                current.push(name);
+                match current.symlink_metadata() {
+                    Ok(meta) if meta.file_type().is_symlink() => return Err(symlink_error()),
+                    Ok(_) => {}
+                    // Nothing below a missing path can be a symlink
+                    Err(_) => break,
+                }
+            }
+            Ok(real)
+        }
+        SymlinkPolicy::WithinRoot => {
+            // Nothing can escape a folder that doesn't exist yet
+            let Ok(real_root) = root.canonicalize() else {
+                return Ok(real);
+            };
+
+            // Symlinks can only redirect the part of the path which exists
+            let mut existing = resolved.as_path();
+            while existing.symlink_metadata().is_err() {
+                existing = match existing.parent() {
+                    Some(parent) => parent,
+                    None => return Ok(real),
+                };
+            }
+
+            // Dangling symlinks fail to canonicalize, they could point anywhere
+            match existing.canonicalize() {
+                Ok(target) if target.starts_with(&real_root) => Ok(real),
+                _ => Err(symlink_error()),
+            }
+        }
+    }
+}
+
+/// Canonicalizes a path that may not exist yet, by canonicalizing the longest
+/// part of it which does and appending the rest.
+fn canonical(path: &Path) -> PathBuf {
+    let mut existing = path;
+
+    loop {
+        if let Ok(real) = existing.canonicalize() {
+            let rest = path.strip_prefix(existing).unwrap_or(Path::new(""));
+            return match rest.as_os_str().is_empty() {
+                true => real,
+                false => real.join(rest),
+            };
+        }
+
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return path.to_path_buf(),
+        };
+    }
+}
+
+fn symlink_error() -> Error {
+    Error::new(ErrorKind::PermissionDenied, "Path goes through a forbidden symlink")
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::paths::{resolve, SymlinkPolicy};
+    use std::fs::{create_dir_all, write};
+    use std::io::ErrorKind;
+    use std::path::{Path, PathBuf};
+    use tempfile::{tempdir, TempDir};
+
+    const POLICIES: [SymlinkPolicy; 3] = [
+        SymlinkPolicy::WithinRoot,
+        SymlinkPolicy::Deny,
+        SymlinkPolicy::Allow,
+    ];
+
+    fn setup() -> (TempDir, PathBuf) {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let root = dir.join("notes");
+        create_dir_all(root.join("projects")).unwrap();
+        create_dir_all(dir.join("secrets")).unwrap();
+        write(root.join("projects/plan.md"), "# Plan").unwrap();
+        write(dir.join("secrets/passwords.md"), "hunter2").unwrap();
+
+        (temp, root)
+    }
+
+    fn error(root: &Path, path: &str, policy: SymlinkPolicy) -> Option<ErrorKind> {
+        resolve(root, path, policy).err().map(|e| e.kind())
+    }
+
+    #[test]
+    fn plain_paths() {
+        let root = Path::new("/data/bob/notes");
+
+        for policy in POLICIES {
+            assert_eq!(
+                resolve(root, "projects/plan.md", policy).unwrap(),
+                root.join("projects/plan.md")
+            );
+            assert_eq!(
+                resolve(root, "./projects/./plan.md", policy).unwrap(),
+                root.join("projects/plan.md")
+            );
+            assert_eq!(resolve(root, "", policy).unwrap(), root);
+            assert_eq!(resolve(root, ".", policy).unwrap(), root);
+            assert_eq!(resolve(root, "a", policy).unwrap(), root.join("a"));
+            assert_eq!(
+                resolve(root, "projects/", policy).unwrap(),
+                root.join("projects")
+            );
+            assert_eq!(
+                resolve(root, "with spaces/ünïcödé.md", policy).unwrap(),
+                root.join("with spaces/ünïcödé.md")
+            );
+        }
+    }
+
+    #[test]
+    fn traversal() {
+        let root = Path::new("/data/bob/notes");
+
+        for policy in POLICIES {
+            for path in [
+                "..",
+                "../alice/notes/index.md",
+                "projects/../../alice",
+                "projects/../plan.md",
+                "/etc/passwd",
+                "//etc/passwd",
+                "nul\0byte.md",
+            ] {
+                assert_eq!(
+                    error(root, path, policy),
+                    Some(ErrorKind::InvalidInput),
+                    "{path}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missing_paths() {
+        let (temp, root) = setup();
+        let dir = temp.path().canonicalize().unwrap();
+        let root = root.canonicalize().unwrap();
+
+        for policy in POLICIES {
+            assert_eq!(
+                resolve(&root, "new/folder/note.md", policy).unwrap(),
+                root.join("new/folder/note.md")
+            );
+            // A user without any notes yet
+            let missing = dir.join("missing");
+            assert_eq!(
+                resolve(&missing, "index.md", policy).unwrap(),
+                missing.join("index.md")
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_within_root() {
+        use std::os::unix::fs::symlink;
+        let (_temp, root) = setup();
+        let real_root = root.canonicalize().unwrap();
+        symlink(root.join("projects"), root.join("linked")).unwrap();
+        symlink("plan.md", root.join("projects/latest.md")).unwrap();
+
+        // Paths are resolved to where the symlinks point
+        for (path, target) in [
+            ("linked/plan.md", "projects/plan.md"),
+            ("projects/latest.md", "projects/plan.md"),
+            ("linked/new.md", "projects/new.md"),
+        ] {
+            for policy in [SymlinkPolicy::WithinRoot, SymlinkPolicy::Allow] {
+                assert_eq!(resolve(&root, path, policy).unwrap(), real_root.join(target));
+            }
+            assert_eq!(
+                error(&root, path, SymlinkPolicy::Deny),
+                Some(ErrorKind::PermissionDenied),
+                "{path}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_outside_root() {
+        use std::os::unix::fs::symlink;
+        let (temp, root) = setup();
+        let dir = temp.path().canonicalize().unwrap();
+        symlink(dir.join("secrets"), root.join("secrets")).unwrap();
+        symlink(dir.join("secrets/passwords.md"), root.join("passwords.md")).unwrap();
+        symlink(dir.join("missing.md"), root.join("dangling.md")).unwrap();
+        symlink("..", root.join("projects/up")).unwrap();
+
+        for (path, target) in [
+            ("secrets/passwords.md", "secrets/passwords.md"),
+            ("secrets/new.md", "secrets/new.md"),
+            ("secrets", "secrets"),
+            ("passwords.md", "secrets/passwords.md"),
+            ("dangling.md", "notes/dangling.md"),
+            ("projects/up/secrets/passwords.md", "secrets/passwords.md"),
+        ] {
+            for policy in [SymlinkPolicy::WithinRoot, SymlinkPolicy::Deny] {
+                assert_eq!(
+                    error(&root, path, policy),
+                    Some(ErrorKind::PermissionDenied),
+                    "{path}"
+                );
+            }
+            assert_eq!(resolve(&root, path, SymlinkPolicy::Allow).unwrap(), dir.join(target));
+        }
+
+        // Links back into the root are fine
+        let inside = "projects/up/notes/index.md";
+        assert!(resolve(&root, inside, SymlinkPolicy::WithinRoot).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_root() {
+        use std::os::unix::fs::symlink;
+        let (temp, root) = setup();
+        let dir = temp.path();
+        let linked_root = dir.join("linked-notes");
+        symlink(&root, &linked_root).unwrap();
+
+        // Only symlinks below the root are subject to the policy
+        for policy in POLICIES {
+            assert_eq!(
+                resolve(&linked_root, "projects/plan.md", policy).unwrap(),
+                root.canonicalize().unwrap().join("projects/plan.md")
+            );
+        }
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!("deny".parse(), Ok(SymlinkPolicy::Deny));
+        assert_eq!(" Allow ".parse(), Ok(SymlinkPolicy::Allow));
+        assert_eq!("within_root".parse(), Ok(SymlinkPolicy::WithinRoot));
+        assert!("follow".parse::<SymlinkPolicy>().is_err());
+        assert_eq!(SymlinkPolicy::default(), SymlinkPolicy::WithinRoot);
+    }
+}
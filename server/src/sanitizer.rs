@@ -0,0 +1,460 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::markdown::{DEFAULT_ID_PREFIX, HIGHLIGHT_CLASS_PREFIX};
+use ammonia::Builder;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// MathML elements kept when sanitizing rendered math.
+const MATHML_TAGS: [&str; 27] = [
+    "math",
+    "semantics",
+    "annotation",
+    "mrow",
+    "mi",
+    "mn",
+    "mo",
+    "ms",
+    "mtext",
+    "mspace",
+    "mfrac",
+    "msqrt",
+    "mroot",
+    "msub",
+    "msup",
+    "msubsup",
+    "munder",
+    "mover",
+    "munderover",
+    "mstyle",
+    "mpadded",
+    "mphantom",
+    "menclose",
+    "merror",
+    "mtable",
+    "mtr",
+    "mtd",
+];
+
+/// Tags which can run scripts, load other pages or restyle the whole page.
+const FORBIDDEN_TAGS: [&str; 14] = [
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "base", "link",
+    "meta", "form", "noscript", "template",
+];
+
+/// Attributes which can run scripts or are managed by the sanitizer itself.
+const FORBIDDEN_ATTRIBUTES: [&str; 5] = ["style", "srcdoc", "formaction", "rel", "class"];
+
+/// URL schemes which can run scripts or read local files.
+const FORBIDDEN_SCHEMES: [&str; 4] = ["javascript", "vbscript", "data", "file"];
+
+/// Links allowed by the strict preset.
+const STRICT_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Set of allowed HTML the rest of the `[sanitizer]` options add to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SanitizerPreset {
+    /// No audio or video and only web and email links
+    Strict,
+    /// Everything notes render to, including embedded audio and video
+    #[default]
+    Default,
+    /// Also keeps media sources and lazy loading images
+    Permissive,
+}
+
+/// The `[sanitizer]` section of the configuration, controlling which HTML is
+/// kept in rendered notes.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SanitizerSettings {
+    pub preset: SanitizerPreset,
+    /// Tags kept on top of the preset's
+    pub extra_tags: Vec<String>,
+    /// Attributes kept per tag, e.g. `abbr = ["title"]`
+    pub tag_attributes: HashMap<String, Vec<String>>,
+    /// Classes kept per tag, e.g. `div = ["warning"]`
+    pub allowed_classes: HashMap<String, Vec<String>>,
+    /// URL schemes links may use on top of the preset's
+    pub url_schemes: Vec<String>,
+    /// Prefix of the ids in notes, keeping them from clashing with the page's own
+    pub id_prefix: String,
+}
+
+impl Default for SanitizerSettings {
+    fn default() -> Self {
+        Self {
+            preset: SanitizerPreset::default(),
+            extra_tags: Vec::new(),
+            tag_attributes: HashMap::new(),
+            allowed_classes: HashMap::new(),
+            url_schemes: Vec::new(),
+            id_prefix: DEFAULT_ID_PREFIX.to_string(),
+        }
+    }
+}
+
+/// Whether a tag or attribute name is made of letters, digits and dashes.
+fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn validate_tag(tag: &str) -> Result<(), String> {
+    if !is_valid_name(tag) {
+        return Err(format!("\"{tag}\" is not a valid lowercase tag name"));
+    }
+    if FORBIDDEN_TAGS.contains(&tag) {
+        return Err(format!("the <{tag}> tag can't be allowed"));
+    }
+    Ok(())
+}
+
+impl SanitizerSettings {
+    /// Checks that the settings can be used to sanitize notes safely.
+    pub fn validate(&self) -> Result<(), String> {
+        let prefix = &self.id_prefix;
+        if !prefix.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "id_prefix \"{prefix}\" must start with a letter and only contain letters, \
+                 digits, - and _"
+            ));
+        }
+
+        for tag in &self.extra_tags {
+            validate_tag(tag)?;
+        }
+
+        for (tag, attributes) in &self.tag_attributes {
+            validate_tag(tag)?;
+
+            for attribute in attributes {
+                if !is_valid_name(attribute) {
+                    return Err(format!("\"{attribute}\" is not a valid lowercase attribute name"));
+                }
+                if attribute.starts_with("on")
+                    || FORBIDDEN_ATTRIBUTES.contains(&attribute.as_str())
+                {
+                    return Err(match attribute.as_str() {
+                        "class" => format!("classes of <{tag}> must be set in allowed_classes"),
+                        _ => format!("the {attribute} attribute can't be allowed"),
+                    });
+                }
+            }
+        }
+
+        for (tag, classes) in &self.allowed_classes {
+            validate_tag(tag)?;
+
+            // Spans keep any class added by the syntax highlighter instead
+            if tag == "span" {
+                return Err("classes of <span> are reserved for syntax highlighting".to_string());
+            }
+            if let Some(class) = classes.iter().find(|class| {
+                class.is_empty() || class.chars().any(|c| c.is_whitespace() || c == '"')
+            }) {
+                return Err(format!("\"{class}\" is not a valid class name"));
+            }
+        }
+
+        for scheme in &self.url_schemes {
+            let valid = scheme.starts_with(|c: char| c.is_ascii_lowercase())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+            if !valid {
+                return Err(format!(
+                    "\"{scheme}\" is not a valid lowercase URL scheme (leave out the colon)"
+                ));
+            }
+            if FORBIDDEN_SCHEMES.contains(&scheme.as_str()) {
+                return Err(format!("{scheme}: URLs can't be allowed"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the sanitizer for rendered notes.
+    ///
+    /// The settings must have been [validated](Self::validate), as ammonia
+    /// panics on some invalid combinations.
+    pub fn builder(&self) -> Builder<'_> {
+        let mut builder = Builder::default();
+
+        // What notes always render to: heading ids, links, callouts, tasks, code and math
+        builder
+            .id_prefix(Some(&self.id_prefix))
+            .add_tag_attributes("h1", &["id"])
+            .add_tag_attributes("h2", &["id"])
+            .add_tag_attributes("h3", &["id"])
+            .add_tag_attributes("h4", &["id"])
+            .add_tag_attributes("h5", &["id"])
+            .add_tag_attributes("h6", &["id"])
+            .add_allowed_classes("a", &["wikilink", "embed", "unresolved", "heading-anchor"])
+            .add_allowed_classes("aside", &["callout"])
+            .add_allowed_classes("details", &["callout"])
+            .add_tag_attributes("aside", &["data-callout"])
+            .add_tag_attributes("details", &["data-callout", "open"])
+            .add_allowed_classes("p", &["callout-title"])
+            .add_allowed_classes("summary", &["callout-title"])
+            .add_allowed_classes("div", &["callout-content"])
+            .add_tags(&["input"])
+            .add_allowed_classes("input", &["task"])
+            .add_tag_attributes("input", &["type", "checked", "disabled", "data-offset"])
+            .add_allowed_classes("pre", &["highlight", "numbered"])
+            .add_tag_attributes("span", &["class"])
+            .attribute_filter(|element, attribute, value| match (element, attribute) {
+                // Only keep the classes added by the syntax highlighter
+                ("span", "class") => {
+                    let classes: Vec<&str> = value
+                        .split_whitespace()
+                        .filter(|class| class.starts_with(HIGHLIGHT_CLASS_PREFIX))
+                        .collect();
+                    (!classes.is_empty()).then(|| classes.join(" ").into())
+                }
+                // Inputs are only kept as task list checkboxes
+                ("input", "type") => (value == "checkbox").then(|| value.into()),
+                _ => Some(value.into()),
+            })
+            .add_allowed_classes("code", &["math-error"])
+            .add_tags(&MATHML_TAGS)
+            .add_tag_attributes("math", &["display"])
+            .add_tag_attributes("mi", &["mathvariant"])
+            .add_tag_attributes("mn", &["mathvariant"])
+            .add_tag_attributes("mtext", &["mathvariant"])
+            .add_tag_attributes(
+                "mo",
+                &[
+                    "fence",
+                    "separator",
+                    "stretchy",
+                    "symmetric",
+                    "largeop",
+                    "movablelimits",
+                    "accent",
+                    "form",
+                    "lspace",
+                    "rspace",
+                    "minsize",
+                    "maxsize",
+                ],
+            )
+            .add_tag_attributes("mstyle", &["displaystyle", "scriptlevel", "mathvariant"])
+            .add_tag_attributes("mfrac", &["linethickness"])
+            .add_tag_attributes("mover", &["accent"])
+            .add_tag_attributes("munder", &["accentunder"])
+            .add_tag_attributes("munderover", &["accent", "accentunder"])
+            .add_tag_attributes("mspace", &["width", "height", "depth"])
+            .add_tag_attributes(
+                "mpadded",
+                &["width", "height", "depth", "lspace", "voffset"],
+            )
+            .add_tag_attributes(
+                "mtable",
+                &[
+                    "columnalign",
+                    "rowalign",
+                    "columnspacing",
+                    "rowspacing",
+                    "columnlines",
+                    "rowlines",
+                    "frame",
+                ],
+            )
+            .add_tag_attributes("mtr", &["columnalign", "rowalign"])
+            .add_tag_attributes("mtd", &["columnalign", "rowalign", "columnspan", "rowspan"])
+            .add_tag_attributes("menclose", &["notation"])
+            .add_tag_attributes("annotation", &["encoding"]);
+
+        match self.preset {
+            SanitizerPreset::Strict => {
+                builder.url_schemes(HashSet::from(STRICT_SCHEMES));
+            }
+            SanitizerPreset::Default | SanitizerPreset::Permissive => {
+                builder
+                    .add_tags(&["audio", "video"])
+                    .add_tag_attributes("audio", &["src", "controls"])
+                    .add_tag_attributes(
+                        "video",
+                        &["src", "autoplay", "loop", "controls", "muted", "width"],
+                    );
+            }
+        }
+
+        if self.preset == SanitizerPreset::Permissive {
+            builder
+                .add_tags(&["picture", "source", "track"])
+                .add_tag_attributes("source", &["src", "srcset", "type", "media"])
+                .add_tag_attributes("track", &["src", "kind", "srclang", "label", "default"])
+                .add_tag_attributes("img", &["loading"])
+                .add_tag_attributes("video", &["height", "poster", "playsinline"]);
+        }
+
+        builder
+            .add_tags(&self.extra_tags)
+            .add_url_schemes(&self.url_schemes);
+        for (tag, attributes) in &self.tag_attributes {
+            builder.add_tag_attributes(tag, attributes);
+        }
+        for (tag, classes) in &self.allowed_classes {
+            builder.add_allowed_classes(tag, classes);
+        }
+
+        builder
+    }
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::sanitizer::{SanitizerPreset, SanitizerSettings};
+    use std::collections::HashMap;
+
+    fn clean(settings: &SanitizerSettings, html: &str) -> String {
+        settings.builder().clean(html).to_string()
+    }
+
+    #[test]
+    fn presets() {
+        let video = r#"<video src="/bob/resources/talk.mp4" controls=""></video>"#;
+        let links = r#"<a href="ftp://example.com/a">a</a><a href="https://example.com">b</a>"#;
+        let lazy = r#"<img src="a.png" loading="lazy">"#;
+
+        let default = SanitizerSettings::default();
+        assert_eq!(clean(&default, video), video);
+        assert!(clean(&default, links).contains("ftp://"));
+        assert_eq!(clean(&default, lazy), r#"<img src="a.png">"#);
+
+        let strict = SanitizerSettings {
+            preset: SanitizerPreset::Strict,
+            ..Default::default()
+        };
+        assert_eq!(clean(&strict, video), "");
+        assert!(!clean(&strict, links).contains("ftp://"));
+        assert!(clean(&strict, links).contains("https://example.com"));
+
+        let permissive = SanitizerSettings {
+            preset: SanitizerPreset::Permissive,
+            ..Default::default()
+        };
+        assert_eq!(clean(&permissive, lazy), lazy);
+        assert_eq!(
+            clean(
+                &permissive,
+                r#"<video controls=""><source src="a.webm" type="video/webm"></video>"#
+            ),
+            r#"<video controls=""><source src="a.webm" type="video/webm"></video>"#
+        );
+
+        for preset in [
+            SanitizerPreset::Strict,
+            SanitizerPreset::Default,
+            SanitizerPreset::Permissive,
+        ] {
+            let settings = SanitizerSettings {
+                preset,
+                ..Default::default()
+            };
+            assert!(settings.validate().is_ok());
+            assert_eq!(
+                clean(&settings, "<h2 id=\"a\">A</h2>"),
+                "<h2 id=\"ln-a\">A</h2>"
+            );
+            assert_eq!(clean(&settings, "<script>alert(1)</script>"), "");
+        }
+    }
+
+    #[test]
+    fn extra_html() {
+        let settings = SanitizerSettings {
+            extra_tags: vec!["meter".to_string()],
+            tag_attributes: HashMap::from([("meter".to_string(), vec!["value".to_string()])]),
+            allowed_classes: HashMap::from([("div".to_string(), vec!["warning".to_string()])]),
+            url_schemes: vec!["obsidian".to_string()],
+            id_prefix: "note-".to_string(),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        assert_eq!(
+            clean(
+                &settings,
+                r#"<meter value="0.5" onclick="x()">half</meter>"#
+            ),
+            r#"<meter value="0.5">half</meter>"#
+        );
+        assert_eq!(
+            clean(&settings, r#"<div class="warning evil">!</div>"#),
+            r#"<div class="warning">!</div>"#
+        );
+        assert!(clean(&settings, r#"<a href="obsidian://open">a</a>"#).contains("obsidian://"));
+        assert_eq!(
+            clean(&settings, r#"<h1 id="a">A</h1>"#),
+            r#"<h1 id="note-a">A</h1>"#
+        );
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let invalid = [
+            SanitizerSettings {
+                id_prefix: String::new(),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                id_prefix: "1-".to_string(),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                id_prefix: "ln\"".to_string(),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                extra_tags: vec!["script".to_string()],
+                ..Default::default()
+            },
+            SanitizerSettings {
+                extra_tags: vec!["Mark".to_string()],
+                ..Default::default()
+            },
+            SanitizerSettings {
+                tag_attributes: HashMap::from([("a".to_string(), vec!["onclick".to_string()])]),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                tag_attributes: HashMap::from([("div".to_string(), vec!["class".to_string()])]),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                tag_attributes: HashMap::from([("style".to_string(), vec!["media".to_string()])]),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                allowed_classes: HashMap::from([("span".to_string(), vec!["a".to_string()])]),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                allowed_classes: HashMap::from([("div".to_string(), vec!["a b".to_string()])]),
+                ..Default::default()
+            },
+            SanitizerSettings {
+                url_schemes: vec!["javascript".to_string()],
+                ..Default::default()
+            },
+            SanitizerSettings {
+                url_schemes: vec!["obsidian:".to_string()],
+                ..Default::default()
+            },
+        ];
+
+        for settings in invalid {
+            assert!(settings.validate().is_err(), "{settings:?}");
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use cfg_if::cfg_if;
 
 cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::paths::SymlinkPolicy;
+use crate::sanitizer::SanitizerSettings;
 use leptos::logging;
 use serde::Deserialize;
 use std::env;
@@ -11,12 +13,71 @@ use std::fs::read_to_string;
 pub struct LazyNotesConfiguration {
     pub settings: LazyNotesSettings,
     pub database: DatabaseSettings,
+    #[serde(default)]
+    pub sanitizer: SanitizerSettings,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct LazyNotesSettings {
     pub data_dir: String,
     pub enable_registration: bool,
+    /// Largest accepted file in bytes, checked for every file of an upload
+    #[serde(default = "default_max_upload_size")]
+    pub max_upload_size: u64,
+    /// Most files accepted in a single upload
+    #[serde(default = "default_max_upload_files")]
+    pub max_upload_files: usize,
+    /// MIME types accepted for uploads (e.g. "image/*" or "application/pdf")
+    #[serde(default = "default_allowed_upload_types")]
+    pub allowed_upload_types: Vec<String>,
+    /// Days deleted notes stay in the trash before being purged (0 keeps them forever)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Commit notes saved in the browser if the notes directory is a git repository
+    #[serde(default)]
+    pub git_auto_commit: bool,
+    /// Snapshots kept per note when notes are not in git (0 disables snapshots)
+    #[serde(default = "default_history_max_snapshots")]
+    pub history_max_snapshots: usize,
+    /// Days snapshots are kept for (0 keeps them until there are too many)
+    #[serde(default = "default_history_max_age_days")]
+    pub history_max_age_days: u64,
+    /// Rendered notes kept in memory (0 disables the cache)
+    #[serde(default = "default_render_cache_capacity")]
+    pub render_cache_capacity: usize,
+    /// Whether symlinks in notes and resources are followed
+    #[serde(default)]
+    pub symlink_policy: SymlinkPolicy,
+}
+
+fn default_max_upload_size() -> u64 {
+    25 * 1024 * 1024
+}
+
+fn default_max_upload_files() -> usize {
+    20
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+fn default_history_max_snapshots() -> usize {
+    50
+}
+
+fn default_history_max_age_days() -> u64 {
+    90
+}
+
+fn default_render_cache_capacity() -> usize {
+    256
+}
+
+fn default_allowed_upload_types() -> Vec<String> {
+    ["image/*", "video/*", "audio/*", "application/pdf", "text/plain"]
+        .map(String::from)
+        .to_vec()
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -33,6 +94,7 @@ pub fn get_configuration(path: Option<String>) -> Option<LazyNotesConfiguration>
     enum Errors {
         IoError,
         ParseError,
+        InvalidSanitizer,
     }
 
     let config_str = match path {
@@ -51,6 +113,13 @@ pub fn get_configuration(path: Option<String>) -> Option<LazyNotesConfiguration>
                 Errors::ParseError
             })
         })
+        .and_then(|config| match config.sanitizer.validate() {
+            Ok(()) => Ok(config),
+            Err(err) => {
+                logging::error!("Invalid [sanitizer] configuration: {err}");
+                Err(Errors::InvalidSanitizer)
+            }
+        })
         .map(|mut config| {
             // Override settings with env variables
             if let Ok(data_dir) = env::var("LN_DATA_DIR") {
@@ -65,6 +134,72 @@ pub fn get_configuration(path: Option<String>) -> Option<LazyNotesConfiguration>
                 }
             }
 
+            if let Ok(max_upload_size) = env::var("LN_MAX_UPLOAD_SIZE") {
+                match max_upload_size.parse() {
+                    Ok(size) => config.settings.max_upload_size = size,
+                    Err(err) => logging::warn!("Invalid LN_MAX_UPLOAD_SIZE: {err}"),
+                }
+            }
+
+            if let Ok(max_upload_files) = env::var("LN_MAX_UPLOAD_FILES") {
+                match max_upload_files.parse() {
+                    Ok(files) => config.settings.max_upload_files = files,
+                    Err(err) => logging::warn!("Invalid LN_MAX_UPLOAD_FILES: {err}"),
+                }
+            }
+
+            if let Ok(allowed_upload_types) = env::var("LN_ALLOWED_UPLOAD_TYPES") {
+                config.settings.allowed_upload_types = allowed_upload_types
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|mime| !mime.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+
+            if let Ok(trash_retention_days) = env::var("LN_TRASH_RETENTION_DAYS") {
+                match trash_retention_days.parse() {
+                    Ok(days) => config.settings.trash_retention_days = days,
+                    Err(err) => logging::warn!("Invalid LN_TRASH_RETENTION_DAYS: {err}"),
+                }
+            }
+
+            if let Ok(git_auto_commit) = env::var("LN_GIT_AUTO_COMMIT") {
+                if git_auto_commit.eq_ignore_ascii_case("true") || git_auto_commit == "1" {
+                    config.settings.git_auto_commit = true;
+                } else if git_auto_commit.eq_ignore_ascii_case("false") || git_auto_commit == "0" {
+                    config.settings.git_auto_commit = false;
+                }
+            }
+
+            if let Ok(history_max_snapshots) = env::var("LN_HISTORY_MAX_SNAPSHOTS") {
+                match history_max_snapshots.parse() {
+                    Ok(count) => config.settings.history_max_snapshots = count,
+                    Err(err) => logging::warn!("Invalid LN_HISTORY_MAX_SNAPSHOTS: {err}"),
+                }
+            }
+
+            if let Ok(history_max_age_days) = env::var("LN_HISTORY_MAX_AGE_DAYS") {
+                match history_max_age_days.parse() {
+                    Ok(days) => config.settings.history_max_age_days = days,
+                    Err(err) => logging::warn!("Invalid LN_HISTORY_MAX_AGE_DAYS: {err}"),
+                }
+            }
+
+            if let Ok(render_cache_capacity) = env::var("LN_RENDER_CACHE_CAPACITY") {
+                match render_cache_capacity.parse() {
+                    Ok(capacity) => config.settings.render_cache_capacity = capacity,
+                    Err(err) => logging::warn!("Invalid LN_RENDER_CACHE_CAPACITY: {err}"),
+                }
+            }
+
+            if let Ok(symlink_policy) = env::var("LN_SYMLINK_POLICY") {
+                match symlink_policy.parse() {
+                    Ok(policy) => config.settings.symlink_policy = policy,
+                    Err(err) => logging::warn!("Invalid LN_SYMLINK_POLICY: {err}"),
+                }
+            }
+
             if let Ok(db_host) = env::var("LN_DB_HOST") {
                 config.database.db_host = db_host;
             }
@@ -94,6 +229,8 @@ pub fn get_configuration(path: Option<String>) -> Option<LazyNotesConfiguration>
 #[cfg(feature = "ssr")]
 #[cfg(test)]
 mod tests {
+    use std::fs::{read_to_string, remove_file, write};
+
     #[allow(dead_code)]
     fn get_settings_file() -> &'static str {
         "tests/test_settings.toml"
@@ -114,5 +251,51 @@ mod tests {
         let ln_config = get_configuration(Some(get_settings_file().to_string())).unwrap();
         assert_eq!(ln_config.database.db_host, "localhost:8000");
         assert_eq!(ln_config.settings.data_dir, "tests/notes");
+        assert_eq!(ln_config.settings.max_upload_size, 25 * 1024 * 1024);
+        assert_eq!(ln_config.settings.max_upload_files, 20);
+        assert!(ln_config
+            .settings
+            .allowed_upload_types
+            .contains(&"image/*".to_string()));
+        assert_eq!(ln_config.settings.trash_retention_days, 30);
+        assert!(!ln_config.settings.git_auto_commit);
+        assert_eq!(ln_config.settings.history_max_snapshots, 50);
+        assert_eq!(ln_config.settings.history_max_age_days, 90);
+        assert_eq!(ln_config.settings.render_cache_capacity, 256);
+        assert_eq!(
+            ln_config.settings.symlink_policy,
+            crate::paths::SymlinkPolicy::WithinRoot
+        );
+        assert_eq!(ln_config.sanitizer, Default::default());
+    }
+
+    #[test]
+    fn sanitizer_configuration() {
+        use crate::sanitizer::SanitizerPreset;
+        use crate::settings::get_configuration;
+        let settings = read_to_string(get_settings_file()).unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("settings.toml");
+        let parse = |sanitizer: &str| {
+            write(&path, format!("{settings}\n[sanitizer]\n{sanitizer}")).unwrap();
+            get_configuration(Some(path.to_string_lossy().into_owned()))
+        };
+
+        let ln_config = parse(
+            "preset = \"strict\"\nurl_schemes = [\"obsidian\"]\n\
+             [sanitizer.allowed_classes]\ndiv = [\"warning\"]",
+        )
+        .unwrap();
+        assert_eq!(ln_config.sanitizer.preset, SanitizerPreset::Strict);
+        assert_eq!(ln_config.sanitizer.url_schemes, vec!["obsidian"]);
+        assert_eq!(ln_config.sanitizer.id_prefix, "ln-");
+
+        // Unsafe or mistyped settings are rejected
+        assert_eq!(parse("extra_tags = [\"script\"]"), None);
+        assert_eq!(parse("url_schemes = [\"javascript\"]"), None);
+        assert_eq!(parse("preset = \"lax\""), None);
+        assert_eq!(parse("extra_tag = [\"mark\"]"), None);
+
+        remove_file(&path).unwrap();
     }
 }
@@ -3,7 +3,10 @@ use cfg_if::cfg_if;
 cfg_if! {
     if #[cfg(feature = "ssr")] {
         use axum::extract::FromRef;
+        use crate::cache::RenderCache;
+        use crate::index::NotesIndex;
         use crate::settings::LazyNotesSettings;
+        use crate::watcher::NoteWatcher;
         use leptos::LeptosOptions;
         use leptos_router::RouteListing;
         use surrealdb::{engine::remote::ws::Client, Surreal};
@@ -14,6 +17,9 @@ cfg_if! {
             pub settings: LazyNotesSettings,
             pub pool: Surreal<Client>,
             pub routes: Vec<RouteListing>,
+            pub index: NotesIndex,
+            pub render_cache: RenderCache,
+            pub watcher: NoteWatcher,
         }
     }
 }
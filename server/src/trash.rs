@@ -0,0 +1,331 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::TrashEntry;
+use crate::history;
+use crate::paths::{self, SymlinkPolicy};
+use leptos::logging;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir, create_dir_all, read_dir, read_to_string, remove_dir_all, rename, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the file recording where a trashed entry came from.
+const INFO_FILE: &str = "info.json";
+
+/// Folder holding the snapshots of a trashed entry, hidden so it can't clash
+/// with the name of the entry.
+const SNAPSHOTS_DIR: &str = ".history";
+
+/// Metadata stored next to every trashed entry.
+#[derive(Debug, Serialize, Deserialize)]
+struct TrashInfo {
+    /// Original path relative to the notes directory
+    path: String,
+    /// Deletion time in seconds since the unix epoch
+    deleted: i64,
+}
+
+/// Trash folder of a user, kept next to (not inside) their notes.
+pub fn trash_path(data_dir: &str, username: &str) -> PathBuf {
+    PathBuf::from(format!("{data_dir}/{username}/.trash"))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Folder of a trashed entry, rejecting ids that could escape the trash.
+fn entry_dir(trash_dir: &Path, id: &str) -> std::io::Result<PathBuf> {
+    let mut components = Path::new(id).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if !name.to_string_lossy().starts_with('.') => {
+            Ok(trash_dir.join(name))
+        }
+        _ => Err(Error::new(ErrorKind::InvalidInput, "Invalid trash entry")),
+    }
+}
+
+/// Moves `full_path` (the note or folder at `path`) into the trash and
+/// returns the id of the new trash entry.
+///
+/// Its snapshots in `history_dir`, if notes are snapshotted, go along so they
+/// come back when the entry is restored.
+pub fn move_to_trash(
+    trash_dir: &Path,
+    path: &str,
+    full_path: &Path,
+    history_dir: Option<&Path>,
+) -> std::io::Result<String> {
+    create_dir_all(trash_dir)?;
+
+    let deleted = now();
+    let name = full_path
+        .file_name()
+        .ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
+
+    // Several entries may be deleted within the same second
+    let mut i = 0;
+    let (id, dir) = loop {
+        let id = format!("{deleted}-{i}");
+        let dir = trash_dir.join(&id);
+
+        match create_dir(&dir) {
+            Ok(()) => break (id, dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => i += 1,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let info = TrashInfo {
+        path: path.to_string(),
+        deleted,
+    };
+    let moved = serde_json::to_vec(&info)
+        .map_err(Error::other)
+        .and_then(|info| write(dir.join(INFO_FILE), info))
+        .and_then(|_| rename(full_path, dir.join(name)));
+
+    if let Err(e) = moved {
+        let _ = remove_dir_all(&dir);
+        return Err(e);
+    }
+
+    // The entry is trashed either way, failures only cost history
+    if let Some(history_dir) = history_dir {
+        if let Err(e) = history::take_snapshots(history_dir, path, &dir.join(SNAPSHOTS_DIR)) {
+            logging::warn!("Failed to trash the snapshots of {path}: {e}");
+        }
+    }
+
+    Ok(id)
+}
+
+/// Reads a trash entry, returning its metadata and the path of the trashed item.
+fn read_entry(trash_dir: &Path, id: &str) -> std::io::Result<(TrashInfo, PathBuf)> {
+    let dir = entry_dir(trash_dir, id)?;
+    let info: TrashInfo = serde_json::from_str(&read_to_string(dir.join(INFO_FILE))?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let item = read_dir(&dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .is_some_and(|name| name != INFO_FILE && name != SNAPSHOTS_DIR)
+        })
+        .ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+
+    Ok((info, item))
+}
+
+/// Lists the entries in a trash folder, most recently deleted first.
+pub fn list_trash(trash_dir: &Path) -> Vec<TrashEntry> {
+    let Ok(entries) = read_dir(trash_dir) else {
+        return Vec::new();
+    };
+
+    let mut trash: Vec<TrashEntry> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let (info, item) = read_entry(trash_dir, &id).ok()?;
+
+            Some(TrashEntry {
+                id,
+                path: info.path,
+                deleted: info.deleted,
+                is_dir: item.is_dir(),
+            })
+        })
+        .collect();
+
+    // Ids end in a counter for entries deleted within the same second
+    let counter = |id: &str| id.rsplit_once('-').and_then(|(_, i)| i.parse::<u64>().ok());
+    trash.sort_by(|a, b| {
+        b.deleted
+            .cmp(&a.deleted)
+            .then_with(|| counter(&b.id).cmp(&counter(&a.id)))
+    });
+    trash
+}
+
+/// Moves a trashed entry back to where it was deleted from and returns its path.
+///
+/// Fails with `AlreadyExists` rather than overwriting anything created since.
+/// The original path is resolved with `policy`, as folders on the way may
+/// have been replaced by symlinks since. Snapshots trashed along with the
+/// entry are put back in `history_dir`.
+pub fn restore(
+    trash_dir: &Path,
+    notes_dir: &Path,
+    id: &str,
+    policy: SymlinkPolicy,
+    history_dir: Option<&Path>,
+) -> std::io::Result<String> {
+    let (info, item) = read_entry(trash_dir, id)?;
+    let target = paths::resolve(notes_dir, &info.path, policy)?;
+
+    if target.symlink_metadata().is_ok() {
+        return Err(ErrorKind::AlreadyExists.into());
+    }
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+    }
+
+    rename(item, &target)?;
+    let dir = entry_dir(trash_dir, id)?;
+    if let Some(history_dir) = history_dir {
+        if let Err(e) = history::put_snapshots(history_dir, &dir.join(SNAPSHOTS_DIR), &info.path) {
+            logging::warn!("Failed to restore the snapshots of {}: {e}", info.path);
+        }
+    }
+    remove_dir_all(dir)?;
+
+    Ok(info.path)
+}
+
+/// Permanently deletes a trashed entry.
+pub fn purge(trash_dir: &Path, id: &str) -> std::io::Result<()> {
+    remove_dir_all(entry_dir(trash_dir, id)?)
+}
+
+/// Permanently deletes the entries trashed more than `max_age` seconds before
+/// `now`, returning how many were removed.
+pub fn purge_older_than(trash_dir: &Path, max_age: i64, now: i64) -> usize {
+    list_trash(trash_dir)
+        .into_iter()
+        .filter(|entry| now - entry.deleted > max_age)
+        .filter(|entry| purge(trash_dir, &entry.id).is_ok())
+        .count()
+}
+
+/// Purges the expired trash of every user in the data directory.
+pub fn purge_expired(data_dir: &str, retention_days: u64) -> usize {
+    let Ok(users) = read_dir(data_dir) else {
+        return 0;
+    };
+    let max_age = i64::try_from(retention_days.saturating_mul(24 * 60 * 60)).unwrap_or(i64::MAX);
+    let now = now();
+
+    users
+        .flatten()
+        .map(|user| purge_older_than(&user.path().join(".trash"), max_age, now))
+        .sum()
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::history::{list_snapshots, save_snapshot, Retention};
+    use crate::paths::SymlinkPolicy;
+    use crate::trash::{list_trash, move_to_trash, purge, purge_older_than, restore};
+    use std::fs::{create_dir_all, read_to_string, write};
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup() -> (TempDir, PathBuf, PathBuf) {
+        let root = tempdir().unwrap();
+        let notes = root.path().join("notes");
+        create_dir_all(notes.join("projects")).unwrap();
+        write(notes.join("projects/plan.md"), "# Plan").unwrap();
+        write(notes.join("todo.md"), "# Todo").unwrap();
+
+        let trash = root.path().join(".trash");
+        (root, notes, trash)
+    }
+
+    #[test]
+    fn trash_and_restore() {
+        let (_root, notes, trash) = setup();
+
+        let note = move_to_trash(&trash, "todo.md", &notes.join("todo.md"), None).unwrap();
+        let folder = move_to_trash(&trash, "projects", &notes.join("projects"), None).unwrap();
+        assert!(!notes.join("todo.md").exists());
+        assert!(!notes.join("projects").exists());
+
+        let entries = list_trash(&trash);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, folder);
+        assert_eq!(entries[0].path, "projects");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].path, "todo.md");
+        assert!(!entries[1].is_dir);
+
+        assert_eq!(
+            restore(&trash, &notes, &folder, SymlinkPolicy::WithinRoot, None).unwrap(),
+            "projects"
+        );
+        assert_eq!(
+            read_to_string(notes.join("projects/plan.md")).unwrap(),
+            "# Plan"
+        );
+
+        // Restoring never overwrites a note created since
+        write(notes.join("todo.md"), "# New todo").unwrap();
+        assert_eq!(
+            restore(&trash, &notes, &note, SymlinkPolicy::WithinRoot, None)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(read_to_string(notes.join("todo.md")).unwrap(), "# New todo");
+
+        purge(&trash, &note).unwrap();
+        assert!(list_trash(&trash).is_empty());
+    }
+
+    #[test]
+    fn trashed_snapshots() {
+        let (root, notes, trash) = setup();
+        let history = root.path().join(".history");
+        let retention = Retention {
+            max_count: 5,
+            max_age: 0,
+        };
+        save_snapshot(&history, "projects/plan.md", "# Plan", retention).unwrap();
+
+        let id =
+            move_to_trash(&trash, "projects", &notes.join("projects"), Some(&history)).unwrap();
+        assert!(list_snapshots(&history, "projects/plan.md", "alice").is_empty());
+        assert_eq!(list_trash(&trash)[0].path, "projects");
+
+        let policy = SymlinkPolicy::WithinRoot;
+        restore(&trash, &notes, &id, policy, Some(&history)).unwrap();
+        assert_eq!(
+            list_snapshots(&history, "projects/plan.md", "alice").len(),
+            1
+        );
+        assert!(list_trash(&trash).is_empty());
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let (_root, notes, trash) = setup();
+        move_to_trash(&trash, "todo.md", &notes.join("todo.md"), None).unwrap();
+
+        for id in ["..", "../notes", "/tmp", ".hidden", ""] {
+            assert!(purge(&trash, id).is_err());
+            assert!(restore(&trash, &notes, id, SymlinkPolicy::WithinRoot, None).is_err());
+        }
+        assert!(notes.join("projects/plan.md").exists());
+        assert_eq!(list_trash(&trash).len(), 1);
+    }
+
+    #[test]
+    fn auto_purge() {
+        let (_root, notes, trash) = setup();
+        move_to_trash(&trash, "todo.md", &notes.join("todo.md"), None).unwrap();
+        let deleted = list_trash(&trash)[0].deleted;
+
+        assert_eq!(purge_older_than(&trash, 60, deleted + 60), 0);
+        assert_eq!(purge_older_than(&trash, 60, deleted + 61), 1);
+        assert!(list_trash(&trash).is_empty());
+    }
+}
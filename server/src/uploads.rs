@@ -0,0 +1,533 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use crate::api::{encode_path, ResourceFile};
+use crate::markdown::{AUDIO_EXTENSIONS, IMAGE_EXTENSIONS, VIDEO_EXTENSIONS};
+use crate::paths::{self, SymlinkPolicy};
+use std::fs::{create_dir_all, metadata, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Maximum number of suffixes tried when a file name is taken.
+const MAX_DUPLICATES: usize = 1000;
+
+/// Number of leading bytes needed to tell the type of an uploaded file.
+pub const SNIFF_LENGTH: usize = 512;
+
+/// Turns the name of an uploaded file into a safe file name.
+///
+/// Folders sent by the browser are dropped, as are leading dots so uploads
+/// can't create hidden files.
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim().trim_start_matches('.').trim();
+
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Folder below `resources/` holding the files uploaded while editing a note,
+/// e.g. `projects/plan` for `projects/plan.md`.
+pub fn note_folder(note_path: &str) -> String {
+    let path = note_path.trim_matches('/');
+    path.strip_suffix(".md").unwrap_or(path).to_string()
+}
+
+/// Resolves a folder below the resources directory, creating nothing.
+///
+/// Returns `None` for hidden folders and for paths leaving the directory,
+/// directly or through a symlink `policy` forbids.
+pub fn resolve_folder(
+    resources_dir: &Path,
+    folder: &str,
+    policy: SymlinkPolicy,
+) -> Option<PathBuf> {
+    let folder = folder.trim();
+    let hidden = Path::new(folder).components().any(|component| {
+        matches!(component, Component::Normal(name) if name.to_string_lossy().starts_with('.'))
+    });
+    if hidden {
+        return None;
+    }
+
+    paths::resolve(resources_dir, folder, policy).ok()
+}
+
+/// Checks a MIME type against patterns like `image/png`, `image/*` or `*/*`.
+pub fn is_allowed_type(mime: &str, allowed: &[String]) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim().to_lowercase();
+    let (kind, _) = mime.split_once('/').unwrap_or((&mime, ""));
+
+    allowed.iter().any(|pattern| {
+        let pattern = pattern.trim().to_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some("*") => true,
+            Some(prefix) => prefix == kind,
+            None => pattern == mime,
+        }
+    })
+}
+
+/// Tells the MIME type of a file from its first bytes, like browsers do,
+/// instead of trusting the type the client claims.
+///
+/// Text that isn't recognized is `text/plain`, binary data
+/// `application/octet-stream`.
+pub fn sniff_type(head: &[u8]) -> &'static str {
+    const SIGNATURES: [(&[u8], &str); 12] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"\xff\xfb", "audio/mpeg"),
+        (b"PK\x03\x04", "application/zip"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+
+    match (head.get(..4), head.get(4..8), head.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return "image/webp",
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return "audio/wav",
+        (_, Some(b"ftyp"), Some(b"avif" | b"avis")) => return "image/avif",
+        (_, Some(b"ftyp"), Some(b"qt  ")) => return "video/quicktime",
+        (_, Some(b"ftyp"), Some(b"M4A ")) => return "audio/mp4",
+        (_, Some(b"ftyp"), _) => return "video/mp4",
+        _ => {}
+    }
+
+    // The head may end in the middle of a character
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return "application/octet-stream",
+    };
+    if text.contains('\0') {
+        return "application/octet-stream";
+    }
+
+    // Markup browsers would run scripts in is never plain text
+    const HTML_TAGS: [&str; 7] =
+        ["<!doctype html", "<html", "<head", "<body", "<script", "<iframe", "<!--"];
+    let start = text.trim_start_matches('\u{feff}').trim_start().to_lowercase();
+    if start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")) {
+        "image/svg+xml"
+    } else if HTML_TAGS.iter().any(|tag| start.starts_with(tag)) {
+        "text/html"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Extensions of the types told by [`sniff_type`], the only types uploads
+/// can have.
+const TYPE_EXTENSIONS: [(&str, &[&str]); 19] = [
+    ("image/png", &["png"]),
+    ("image/jpeg", &["jpg", "jpeg"]),
+    ("image/gif", &["gif"]),
+    ("image/bmp", &["bmp"]),
+    ("image/webp", &["webp"]),
+    ("image/avif", &["avif"]),
+    ("image/svg+xml", &["svg"]),
+    ("application/pdf", &["pdf"]),
+    ("video/webm", &["webm", "mkv"]),
+    ("video/mp4", &["mp4", "m4v"]),
+    ("video/quicktime", &["mov"]),
+    ("audio/ogg", &["ogg", "oga", "ogv", "opus"]),
+    ("audio/flac", &["flac"]),
+    ("audio/mpeg", &["mp3"]),
+    ("audio/wav", &["wav"]),
+    ("audio/mp4", &["m4a"]),
+    (
+        "application/zip",
+        &["zip", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub"],
+    ),
+    (
+        "text/plain",
+        &["txt", "md", "markdown", "csv", "tsv", "log", "json", "yaml", "yml", "toml"],
+    ),
+    ("text/html", &["html", "htm", "xhtml"]),
+];
+
+/// Extensions served as active content, which other binary data can't have.
+const SCRIPT_EXTENSIONS: [&str; 5] = ["js", "mjs", "xml", "xsl", "xht"];
+
+/// Whether a file called `name` may hold data of the sniffed type `mime`.
+///
+/// Files are served with the type of their extension, so one that doesn't
+/// fit could be run as a page or script. Binary data of unknown types may
+/// have any extension not claimed by a known type.
+pub fn matches_extension(name: &str, mime: &str) -> bool {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    let claimed_by = TYPE_EXTENSIONS
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+        .map(|(mime, _)| *mime);
+
+    match claimed_by {
+        Some(claimed_by) => claimed_by == mime,
+        None => {
+            mime == "application/octet-stream"
+                && !SCRIPT_EXTENSIONS.contains(&extension.as_str())
+        }
+    }
+}
+
+/// Whether resources of type `mime` can be shown in the browser. Anything
+/// else is downloaded, including SVG images as they can carry scripts.
+pub fn is_inline_type(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim().to_lowercase();
+
+    match mime.split_once('/') {
+        Some(("image", "svg+xml")) => false,
+        Some(("image" | "video" | "audio", _)) => true,
+        _ => mime == "application/pdf",
+    }
+}
+
+/// Files of a single upload, written to hidden staging files until every one
+/// of them was received.
+///
+/// Committing gives them their final names, and dropping the upload before
+/// that removes whatever was staged, so an upload is saved entirely or not at
+/// all.
+#[derive(Debug, Default)]
+pub struct StagedUpload {
+    files: Vec<StagedFile>,
+}
+
+#[derive(Debug)]
+struct StagedFile {
+    dir: PathBuf,
+    name: String,
+    staged: PathBuf,
+}
+
+impl StagedUpload {
+    /// Creates the staging file of a file to be saved as `name` in `dir`.
+    pub fn stage(&mut self, dir: &Path, name: &str) -> std::io::Result<File> {
+        create_dir_all(dir)?;
+
+        for i in 0..MAX_DUPLICATES {
+            let staged = dir.join(format!(".{name}.{i}.upload"));
+            match OpenOptions::new().write(true).create_new(true).open(&staged) {
+                Ok(file) => {
+                    self.files.push(StagedFile {
+                        dir: dir.to_path_buf(),
+                        name: name.to_string(),
+                        staged,
+                    });
+                    return Ok(file);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(ErrorKind::AlreadyExists.into())
+    }
+
+    /// Moves every staged file to its final name, adding a suffix like
+    /// `name (1).png` if the name is taken. Returns the names the files were
+    /// saved as.
+    ///
+    /// Existing files are never overwritten, even by concurrent uploads. If a
+    /// file can't be saved, the ones saved before it are removed again.
+    pub fn commit(mut self) -> std::io::Result<Vec<String>> {
+        let mut saved: Vec<PathBuf> = Vec::new();
+
+        for file in &self.files {
+            match move_unique(&file.staged, &file.dir, &file.name) {
+                Ok(name) => saved.push(file.dir.join(name)),
+                Err(e) => {
+                    for path in &saved {
+                        let _ = remove_file(path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        self.files.clear();
+        Ok(saved
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = remove_file(&file.staged);
+        }
+    }
+}
+
+/// Moves `staged` to `name` in `dir`, or to the first free name with a suffix.
+fn move_unique(staged: &Path, dir: &Path, name: &str) -> std::io::Result<String> {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+
+    for i in 0..MAX_DUPLICATES {
+        let candidate = match i {
+            0 => name.to_string(),
+            i => format!("{stem} ({i}){extension}"),
+        };
+
+        // Claim the name first, so the rename only ever replaces our own file
+        let path = dir.join(&candidate);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+
+        if let Err(e) = rename(staged, &path) {
+            let _ = remove_file(&path);
+            return Err(e);
+        }
+
+        return Ok(candidate);
+    }
+
+    Err(ErrorKind::AlreadyExists.into())
+}
+
+/// Markdown embedding or linking a resource, e.g. `![diagram](/resources/diagram.png)`.
+pub fn resource_markdown(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    let url = format!("/resources/{}", encode_path(path));
+
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        format!("![{stem}]({url})")
+    } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        format!("<video src=\"{url}\" controls></video>")
+    } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+        format!("<audio src=\"{url}\" controls></audio>")
+    } else {
+        format!("[{name}]({url})")
+    }
+}
+
+/// Recursively lists the files in a resources directory, skipping hidden ones.
+pub fn list_resources(dir: &Path) -> Vec<ResourceFile> {
+    let mut files = Vec::new();
+    collect_resources(dir, "", &mut files);
+    files.sort_by_key(|file| file.path.to_lowercase());
+    files
+}
+
+fn collect_resources(dir: &Path, prefix: &str, files: &mut Vec<ResourceFile>) {
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let Ok(meta) = metadata(entry.path()) else {
+            continue;
+        };
+
+        let path = match prefix.is_empty() {
+            true => name,
+            false => format!("{prefix}/{name}"),
+        };
+
+        if meta.is_dir() {
+            collect_resources(&entry.path(), &path, files);
+        } else {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .and_then(|duration| i64::try_from(duration.as_secs()).ok());
+
+            files.push(ResourceFile {
+                markdown: resource_markdown(&path),
+                path,
+                size: meta.len(),
+                modified,
+            });
+        }
+    }
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::paths::SymlinkPolicy;
+    use crate::uploads::{
+        is_allowed_type, is_inline_type, list_resources, matches_extension, note_folder,
+        resolve_folder, resource_markdown, sanitize_file_name, sniff_type, StagedUpload,
+    };
+    use std::fs::read;
+    use std::io::Write;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn save_unique(dir: &Path, name: &str, data: &[u8]) -> std::io::Result<String> {
+        let mut upload = StagedUpload::default();
+        upload.stage(dir, name)?.write_all(data)?;
+        Ok(upload.commit()?.remove(0))
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(sanitize_file_name("photo.png").as_deref(), Some("photo.png"));
+        assert_eq!(sanitize_file_name("C:\\Users\\bob\\photo.png").as_deref(), Some("photo.png"));
+        assert_eq!(sanitize_file_name("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_file_name(".bashrc").as_deref(), Some("bashrc"));
+        assert_eq!(sanitize_file_name("bad\nname.txt").as_deref(), Some("badname.txt"));
+        assert_eq!(sanitize_file_name(".."), None);
+        assert_eq!(sanitize_file_name("folder/"), None);
+    }
+
+    #[test]
+    fn folders() {
+        let root = Path::new("/data/bob/resources");
+        let resolve = |folder| resolve_folder(root, folder, SymlinkPolicy::WithinRoot);
+        assert_eq!(resolve("notes/./images"), Some(root.join("notes/images")));
+        assert_eq!(resolve(""), Some(root.to_path_buf()));
+        assert_eq!(resolve("../notes"), None);
+        assert_eq!(resolve("/etc"), None);
+        assert_eq!(resolve("images/.hidden"), None);
+    }
+
+    #[test]
+    fn note_folders() {
+        assert_eq!(note_folder("index.md"), "index");
+        assert_eq!(note_folder("/projects/plan.md"), "projects/plan");
+        assert_eq!(note_folder("projects/notes.txt"), "projects/notes.txt");
+    }
+
+    #[test]
+    fn mime_types() {
+        let allowed = ["image/*".to_string(), "application/pdf".to_string()];
+        assert!(is_allowed_type("image/png", &allowed));
+        assert!(is_allowed_type("Application/PDF", &allowed));
+        assert!(is_allowed_type("application/pdf; charset=binary", &allowed));
+        assert!(!is_allowed_type("text/html", &allowed));
+        assert!(!is_allowed_type("imagex/png", &allowed));
+        assert!(is_allowed_type("text/html", &["*/*".to_string()]));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+
+        assert_eq!(save_unique(&dir, "photo.png", b"1").unwrap(), "photo.png");
+        assert_eq!(save_unique(&dir, "photo.png", b"2").unwrap(), "photo (1).png");
+        assert_eq!(save_unique(&dir, "photo.png", b"3").unwrap(), "photo (2).png");
+        assert_eq!(save_unique(&dir, "README", b"4").unwrap(), "README");
+        assert_eq!(save_unique(&dir, "README", b"5").unwrap(), "README (1)");
+        assert_eq!(read(dir.join("photo.png")).unwrap(), b"1");
+
+        let paths: Vec<String> = list_resources(&dir).into_iter().map(|f| f.path).collect();
+        assert_eq!(
+            paths,
+            vec!["photo (1).png", "photo (2).png", "photo.png", "README", "README (1)"]
+        );
+    }
+
+    #[test]
+    fn staged_uploads() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        save_unique(&dir, "notes.txt", b"old").unwrap();
+
+        // Files are only visible once the whole upload is committed
+        let mut upload = StagedUpload::default();
+        upload.stage(&dir, "notes.txt").unwrap().write_all(b"new").unwrap();
+        upload.stage(&dir.join("images"), "photo.png").unwrap();
+        assert_eq!(list_resources(&dir).len(), 1);
+        assert_eq!(upload.commit().unwrap(), vec!["notes (1).txt", "photo.png"]);
+        assert_eq!(read(dir.join("notes.txt")).unwrap(), b"old");
+        assert_eq!(read(dir.join("notes (1).txt")).unwrap(), b"new");
+
+        // Abandoned uploads leave nothing behind
+        let mut upload = StagedUpload::default();
+        upload.stage(&dir, "draft.txt").unwrap();
+        drop(upload);
+        let paths: Vec<String> = list_resources(&dir).into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec!["images/photo.png", "notes (1).txt", "notes.txt"]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn sniffed_types() {
+        assert_eq!(sniff_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_type(b"\xff\xd8\xff\xe0\0\x10JFIF"), "image/jpeg");
+        assert_eq!(sniff_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_type(b"\0\0\0\x18ftypmp42"), "video/mp4");
+        assert_eq!(sniff_type(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff_type(b"<?xml version=\"1.0\"?>\n<svg></svg>"), "image/svg+xml");
+        assert_eq!(sniff_type("Grüße\n".as_bytes()), "text/plain");
+        // A character cut off at the end of the sniffed bytes is still text
+        assert_eq!(sniff_type(&"Grüße".as_bytes()[..3]), "text/plain");
+        assert_eq!(sniff_type(b"MZ\x90\0\x03\0"), "application/octet-stream");
+        // Whatever the browser claims, HTML is never taken for plain text
+        assert_eq!(sniff_type(b"\n<HTML><script>alert(1)</script>"), "text/html");
+    }
+
+    #[test]
+    fn extensions() {
+        assert!(matches_extension("photo.JPG", "image/jpeg"));
+        assert!(matches_extension("notes.txt", "text/plain"));
+        assert!(matches_extension("firmware.bin", "application/octet-stream"));
+        assert!(matches_extension("README", "application/octet-stream"));
+        // Text ending in a script would be served as a page
+        assert!(!matches_extension("x.html", "text/plain"));
+        assert!(!matches_extension("x.svg", "text/plain"));
+        assert!(!matches_extension("photo.png", "image/jpeg"));
+        assert!(!matches_extension("x.js", "application/octet-stream"));
+        assert!(!matches_extension("x.txt", "application/octet-stream"));
+
+        assert!(is_inline_type("image/png"));
+        assert!(is_inline_type("video/mp4"));
+        assert!(is_inline_type("application/pdf"));
+        assert!(!is_inline_type("image/svg+xml"));
+        assert!(!is_inline_type("text/html; charset=utf-8"));
+        assert!(!is_inline_type("text/plain"));
+    }
+
+    #[test]
+    fn markdown_snippets() {
+        assert_eq!(
+            resource_markdown("images/my diagram.PNG"),
+            "![my diagram](/resources/images/my%20diagram.PNG)"
+        );
+        assert_eq!(
+            resource_markdown("talk.mp4"),
+            "<video src=\"/resources/talk.mp4\" controls></video>"
+        );
+        assert_eq!(resource_markdown("paper.pdf"), "[paper.pdf](/resources/paper.pdf)");
+    }
+}
@@ -0,0 +1,141 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+use leptos::logging;
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::broadcast;
+
+/// Changes buffered per user before slow listeners start missing some.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A watcher on one user's notes, alive while anyone listens to it.
+struct UserWatcher {
+    // Dropping the watcher stops it
+    _watcher: RecommendedWatcher,
+    changes: broadcast::Sender<String>,
+}
+
+/// Watches the notes of users with a note open, telling listeners which
+/// notes changed on disk.
+#[derive(Clone)]
+pub struct NoteWatcher {
+    data_dir: PathBuf,
+    users: Arc<Mutex<HashMap<String, UserWatcher>>>,
+}
+
+impl std::fmt::Debug for NoteWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NoteWatcher")
+            .field("data_dir", &self.data_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoteWatcher {
+    pub fn new(data_dir: &str) -> Self {
+        Self {
+            data_dir: PathBuf::from(data_dir),
+            users: Arc::default(),
+        }
+    }
+
+    /// Listens for changes to the notes of `username`. Each change is the path
+    /// of the changed file relative to the notes directory.
+    ///
+    /// The user's notes are watched from the first subscription until the
+    /// last receiver is dropped.
+    pub fn subscribe(&self, username: &str) -> notify::Result<broadcast::Receiver<String>> {
+        let mut users = self.users.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Stop watching for users nobody listens to anymore
+        users.retain(|_, user| user.changes.receiver_count() > 0);
+
+        if let Some(user) = users.get(username) {
+            return Ok(user.changes.subscribe());
+        }
+
+        let notes_dir = self.data_dir.join(username).join("notes");
+        let (changes, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+        let sender = changes.clone();
+        let root = notes_dir.clone();
+
+        let mut watcher = recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if is_change(&event.kind) => {
+                for path in event.paths.iter().filter_map(|path| changed_note(&root, path)) {
+                    // Nobody listening is fine, the watcher is dropped on the next subscription
+                    let _ = sender.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => logging::warn!("Failed to watch notes: {e}"),
+        })?;
+        watcher.watch(&notes_dir, RecursiveMode::Recursive)?;
+
+        users.insert(
+            username.to_string(),
+            UserWatcher {
+                _watcher: watcher,
+                changes,
+            },
+        );
+        Ok(receiver)
+    }
+}
+
+/// Whether an event may have changed what a note looks like.
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    )
+}
+
+/// Path of a changed file relative to the notes directory, skipping hidden
+/// files such as the temporary files of atomic writes.
+pub fn changed_note(notes_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(notes_dir).ok()?;
+    let mut parts = Vec::new();
+
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) if !name.to_string_lossy().starts_with('.') => {
+                parts.push(name.to_string_lossy())
+            }
+            _ => return None,
+        }
+    }
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+}}
+
+#[cfg(feature = "ssr")]
+#[cfg(test)]
+mod tests {
+    use crate::watcher::changed_note;
+    use std::path::Path;
+
+    #[test]
+    fn changed_notes() {
+        let notes = Path::new("/data/bob/notes");
+
+        assert_eq!(
+            changed_note(notes, &notes.join("projects/plan.md")).as_deref(),
+            Some("projects/plan.md")
+        );
+        assert_eq!(
+            changed_note(notes, &notes.join("index.md")).as_deref(),
+            Some("index.md")
+        );
+        assert_eq!(changed_note(notes, notes), None);
+        assert_eq!(changed_note(notes, &notes.join(".plan.md.1-2.tmp")), None);
+        assert_eq!(changed_note(notes, &notes.join(".git/index")), None);
+        assert_eq!(
+            changed_note(notes, Path::new("/data/bob/resources/a.png")),
+            None
+        );
+    }
+}